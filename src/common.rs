@@ -4,20 +4,6 @@ pub const SKY_PLAY: URN = URN::service("schemas-nds-com", "SkyPlay", 2);
 pub const SKY_BROWSE: URN = URN::service("schemas-nds-com", "SkyBrowse", 2);
 
 
-pub fn envelope(body: &str) -> String {
-    format!(r#"<?xml version="1.0" encoding="utf-8"?>
-        <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
-            <s:Body>{}</s:Body>
-        </s:Envelope>"#, body)
-}
-
-pub fn as_elements(arguments: &std::collections::HashMap<&str, &str>) -> String {
-    arguments.iter()
-        .map(|(key, value)| format!("<{}>{}</{}>", &key, &value, &key))
-        .collect::<Vec<_>>()
-        .join("")
-}
-
 pub mod errors {
     // Create the Error, ErrorKind, ResultExt, and Result types
     error_chain! {
@@ -29,36 +15,39 @@ pub mod errors {
             Reqwest(reqwest::Error);
             Roxmltree(roxmltree::Error);
             Chrono(chrono::ParseError);
-            SsdpClient(ssdp_client::Error);
         }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use maplit::hashmap;
 
-    #[test]
-    fn test_envelope() {
-        let xml_source = envelope("<test-element/>");
-        let document = roxmltree::Document::parse(xml_source.as_str()).unwrap();
-
-        let body_element = document.root_element().first_element_child().unwrap();
-        let payload_element = body_element.first_element_child().unwrap();
-
-        assert_eq!("Body", body_element.tag_name().name());
-        assert_eq!("test-element", payload_element.tag_name().name());
+        errors {
+            NoSuchObject(code: u32, description: String) {
+                description("No such object")
+                display("No such object (UPnP error {}: {})", code, description)
+            }
+            Busy(code: u32, description: String) {
+                description("SkyBox busy")
+                display("SkyBox busy (UPnP error {}: {})", code, description)
+            }
+            UPnPError(code: u32, description: String) {
+                description("UPnP error")
+                display("UPnP error {}: {}", code, description)
+            }
+            SoapFault(fault_code: String, fault_string: String) {
+                description("SOAP fault")
+                display("SOAP fault {}: {}", fault_code, fault_string)
+            }
+            HttpStatus(status: u16) {
+                description("Unexpected HTTP status")
+                display("Unexpected HTTP status {}", status)
+            }
+        }
     }
 
-    #[test]
-    fn test_as_elements() {
-
-        let str = as_elements(&hashmap!{
-            "foo" => "bar"
-        });
+    impl Error {
 
-        assert_eq!("<foo>bar</foo>", str);
+        /**
+         * Whether the SkyBox could not be reached at all, e.g. as it now has another IP address
+         */
+        pub fn is_connection_failure(&self) -> bool {
+            matches!(self.kind(), ErrorKind::Reqwest(error) if error.is_connect())
+        }
     }
-
-}
\ No newline at end of file
+}
@@ -0,0 +1,104 @@
+use super::common::errors::Result;
+
+use serde::Serialize;
+
+/**
+ * The root of the SkyBrowse content tree
+ */
+pub const ROOT_CONTAINER: &str = "0";
+
+/**
+ * The container holding the recordings, the planner
+ */
+pub const RECORDINGS_CONTAINER: &str = "3";
+
+/**
+ * A container or item in the SkyBrowse content tree, as described by DIDL-Lite
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContentObject {
+    pub id: String,
+    #[serde(rename = "ParentID")]
+    pub parent_id: Option<String>,
+    pub container: bool,
+    pub title: Option<String>,
+    pub class: Option<String>,
+    pub child_count: Option<usize>,
+}
+
+impl ContentObject {
+
+    /**
+     * Parse a `<container>` or `<item>` element, giving `None` for any other element or one without an ID
+     */
+    pub fn parse(elem: roxmltree::Node) -> Option<ContentObject> {
+        let container = match elem.tag_name().name() {
+            "container" => true,
+            "item" => false,
+            _ => return None
+        };
+
+        let text_of = |name: &str| elem.children()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .map(String::from);
+
+        Some(ContentObject {
+            id: elem.attribute("id")?.into(),
+            parent_id: elem.attribute("parentID").map(String::from),
+            container,
+            title: text_of("title"),
+            class: text_of("class"),
+            child_count: elem.attribute("childCount").and_then(|count| count.parse().ok()),
+        })
+    }
+
+    /**
+     * Every container and item of a DIDL-Lite document, in document order
+     */
+    pub fn parse_all(didl_lite: &str) -> Result<Vec<ContentObject>> {
+        let doc = roxmltree::Document::parse(didl_lite)?;
+        Ok(doc.descendants().filter_map(ContentObject::parse).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::RECORDINGS;
+
+    #[test]
+    fn test_parse_all() {
+        let didl_lite = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<container id="3" parentID="0" restricted="1" childCount="4"><dc:title>Planner</dc:title><upnp:class>object.container</upnp:class></container>
+<container id="9" restricted="1"/>
+</DIDL-Lite>"#;
+
+        assert_eq!(vec![
+            ContentObject {
+                id: "3".into(),
+                parent_id: Some("0".into()),
+                container: true,
+                title: Some("Planner".into()),
+                class: Some("object.container".into()),
+                child_count: Some(4),
+            },
+            ContentObject {
+                id: "9".into(),
+                parent_id: None,
+                container: true,
+                title: None,
+                class: None,
+                child_count: None,
+            },
+        ], ContentObject::parse_all(didl_lite).unwrap());
+
+        let objects = ContentObject::parse_all(RECORDINGS).unwrap();
+        assert_eq!(4, objects.len());
+        assert!(!objects[0].container);
+        assert_eq!("BOOK:687878212", objects[0].id);
+        assert_eq!(Some("Ewan McGregor: Cold Chain Mission"), objects[0].title.as_deref());
+        assert_eq!(Some("object.item.videoItem"), objects[0].class.as_deref());
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/**
+ * Identity of a SkyBox, from its UPnP device description
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeviceInfo {
+    pub friendly_name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub udn: Option<String>,
+}
+
+impl DeviceInfo {
+
+    /**
+     * Parse the `device` element of a device description, e.g. `/root/device/friendlyName`
+     */
+    pub fn parse(doc: &roxmltree::Document) -> DeviceInfo {
+        let device = match doc.root_element().children().find(|n| n.tag_name().name() == "device") {
+            Some(device) => device,
+            None => return DeviceInfo::default()
+        };
+
+        let text_of = |name: &str| device.children()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(String::from);
+
+        DeviceInfo {
+            friendly_name: text_of("friendlyName"),
+            manufacturer: text_of("manufacturer"),
+            model_name: text_of("modelName"),
+            model_number: text_of("modelNumber"),
+            serial_number: text_of("serialNumber"),
+            udn: text_of("UDN"),
+        }
+    }
+
+    /**
+     * Whether both describe the same physical box, by UDN or else by serial number
+     */
+    pub fn same_device(&self, other: &DeviceInfo) -> bool {
+        match (&self.udn, &other.udn) {
+            (Some(udn), Some(other_udn)) if udn == other_udn => true,
+            _ => matches!((&self.serial_number, &other.serial_number), (Some(serial), Some(other_serial)) if serial == other_serial)
+        }
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let model = [&self.manufacturer, &self.model_name, &self.model_number].iter()
+            .filter_map(|part| part.as_deref())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        match &self.friendly_name {
+            Some(friendly_name) if model.is_empty() => write!(f, "{}", friendly_name),
+            Some(friendly_name) => write!(f, "{} ({})", friendly_name, model),
+            None => write!(f, "{}", model)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+    <device>
+        <friendlyName>Lounge</friendlyName>
+        <manufacturer>Sky</manufacturer>
+        <modelName>DRX890</modelName>
+        <modelNumber>4F31H7</modelNumber>
+        <serialNumber>0000000000BEEF</serialNumber>
+        <UDN>uuid:444D5376-3247-536B-7953-00000000BEEF</UDN>
+    </device>
+</root>"#;
+
+    #[test]
+    fn test_parse() {
+        let doc = roxmltree::Document::parse(DESCRIPTION).unwrap();
+        let device = DeviceInfo::parse(&doc);
+
+        assert_eq!(Some("Lounge"), device.friendly_name.as_deref());
+        assert_eq!(Some("Sky"), device.manufacturer.as_deref());
+        assert_eq!(Some("DRX890"), device.model_name.as_deref());
+        assert_eq!(Some("4F31H7"), device.model_number.as_deref());
+        assert_eq!(Some("0000000000BEEF"), device.serial_number.as_deref());
+        assert_eq!(Some("uuid:444D5376-3247-536B-7953-00000000BEEF"), device.udn.as_deref());
+        assert_eq!("Lounge (Sky DRX890 4F31H7)", device.to_string());
+
+        let doc = roxmltree::Document::parse("<root/>").unwrap();
+        assert_eq!(DeviceInfo::default(), DeviceInfo::parse(&doc));
+    }
+
+    #[test]
+    fn test_same_device() {
+        let device = DeviceInfo {
+            serial_number: Some("0000000000BEEF".into()),
+            udn: Some("uuid:BEEF".into()),
+            ..DeviceInfo::default()
+        };
+
+        let by_udn = DeviceInfo { udn: Some("uuid:BEEF".into()), ..DeviceInfo::default() };
+        let by_serial = DeviceInfo { serial_number: Some("0000000000BEEF".into()), udn: Some("uuid:CAFE".into()), ..DeviceInfo::default() };
+        let other = DeviceInfo { serial_number: Some("0000000000CAFE".into()), ..DeviceInfo::default() };
+
+        assert!(device.same_device(&by_udn));
+        assert!(device.same_device(&by_serial));
+        assert!(!device.same_device(&other));
+        assert!(!DeviceInfo::default().same_device(&DeviceInfo::default()));
+    }
+}
@@ -0,0 +1,167 @@
+use super::common::errors::*;
+use super::item::{Item, ServiceType};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/**
+ * Criteria selecting a subset of recordings.
+ * Absent criteria match every recording, present criteria must all match.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    pub viewed: Option<bool>,
+    pub channel_name: Option<String>, // Case insensitive
+    pub service_type: Option<ServiceType>,
+    pub series_id: Option<String>,
+    pub title: Option<Regex>,
+
+    pub recorded_since: Option<DateTime<FixedOffset>>, // Inclusive
+    pub recorded_before: Option<DateTime<FixedOffset>>, // Exclusive
+
+    pub min_duration: Option<u64>, //Seconds, inclusive
+    pub max_duration: Option<u64>, //Seconds, inclusive
+}
+
+impl Filter {
+
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        self.viewed.iter().all(|&viewed| item.viewed == viewed) &&
+        self.channel_name.iter().all(|name| item.channel_name.eq_ignore_ascii_case(name)) &&
+        self.service_type.iter().all(|service_type| &item.service_type == service_type) &&
+        self.series_id.iter().all(|series_id| item.series_id.as_ref() == Some(series_id)) &&
+        self.title.iter().all(|title| title.is_match(&item.title)) &&
+        self.recorded_since.iter().all(|since| &item.recorded_starttime >= since) &&
+        self.recorded_before.iter().all(|before| &item.recorded_starttime < before) &&
+        self.min_duration.iter().all(|&min| item.recorded_duration >= min) &&
+        self.max_duration.iter().all(|&max| item.recorded_duration <= max)
+    }
+
+    /**
+     * Keep only the matching items, preserving their order
+     */
+    pub fn apply(&self, items: Vec<Item>) -> Vec<Item> {
+        items.into_iter()
+            .filter(|item| self.matches(item))
+            .collect()
+    }
+}
+
+lazy_static! {
+    static ref UNITS_RE: Regex = Regex::new(r"^(?:(\d+)h)?(?:(\d+)m)?(?:(\d+)s)?$").expect("Cannot compile regex!");
+    static ref CLOCK_RE: Regex = Regex::new(r"^(\d+):(\d{2}):(\d{2})$").expect("Cannot compile regex!");
+}
+
+/**
+ * Parse a date/time either as RFC 3339, e.g. `2021-05-25T21:06:04Z`,
+ * or as a date at local midnight, e.g. `2021-05-25`
+ */
+pub fn parse_date_time(text: &str) -> Result<DateTime<FixedOffset>> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(date_time);
+    }
+
+    let midnight = NaiveDate::parse_from_str(text, "%Y-%m-%d")?
+        .and_hms_opt(0, 0, 0)
+        .ok_or("Cannot build midnight")?;
+    let local = Local.from_local_datetime(&midnight)
+        .earliest()
+        .ok_or(format!("Local midnight does not exist on {}", text))?;
+
+    Ok(local.with_timezone(local.offset()))
+}
+
+/**
+ * Parse a duration in seconds, given as plain seconds, e.g. `90`, with units, e.g. `1h30m`,
+ * or as a clock, e.g. `1:30:00`
+ */
+pub fn parse_duration(text: &str) -> Result<u64> {
+    if let Ok(secs) = text.parse() {
+        return Ok(secs);
+    }
+
+    let caps = UNITS_RE.captures(text)
+        .or_else(|| CLOCK_RE.captures(text))
+        .filter(|caps| caps.iter().skip(1).any(|cap| cap.is_some()))
+        .ok_or(format!("Cannot parse duration: {}", text))?;
+
+    let field = |i| -> Result<u64> {
+        Ok(caps.get(i).map_or(Ok(0), |m| m.as_str().parse())?)
+    };
+
+    Ok(field(1)? * 60 * 60 + field(2)? * 60 + field(3)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::items;
+
+    fn ids(items: &[Item]) -> Vec<&str> {
+        items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_empty_filter_matches_all() {
+        assert_eq!(4, Filter::new().apply(items()).len());
+    }
+
+    #[test]
+    fn test_viewed() {
+        let unviewed = Filter { viewed: Some(false), ..Filter::default() }.apply(items());
+        assert_eq!(vec!["BOOK:688476834", "BOOK:688555858"], ids(&unviewed));
+
+        let viewed = Filter { viewed: Some(true), ..Filter::default() }.apply(items());
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688614341"], ids(&viewed));
+    }
+
+    #[test]
+    fn test_combined_criteria() {
+        let filter = Filter {
+            channel_name: Some("bbc 2 england".into()),
+            service_type: Some(ServiceType::Documentary),
+            title: Some(Regex::new("^Ewan").unwrap()),
+            recorded_since: Some(parse_date_time("2012-04-23").unwrap()),
+            ..Filter::default()
+        };
+        assert_eq!(vec!["BOOK:688476834"], ids(&filter.apply(items())));
+
+        let filter = Filter {
+            series_id: Some("13369".into()),
+            recorded_before: Some(parse_date_time("2012-04-23T00:00:00+01:00").unwrap()),
+            ..Filter::default()
+        };
+        assert_eq!(vec!["BOOK:687878212"], ids(&filter.apply(items())));
+    }
+
+    #[test]
+    fn test_duration() {
+        let filter = Filter { min_duration: Some(60 * 60), ..Filter::default() };
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688555858"], ids(&filter.apply(items())));
+
+        let filter = Filter { max_duration: Some(45 * 60), ..Filter::default() };
+        assert_eq!(vec!["BOOK:688614341"], ids(&filter.apply(items())));
+    }
+
+    #[test]
+    fn test_parse_date_time() {
+        assert_eq!(DateTime::parse_from_rfc3339("2021-05-25T21:06:04Z").unwrap(), parse_date_time("2021-05-25T21:06:04Z").unwrap());
+        assert_eq!("2021-01-01T00:00:00", parse_date_time("2021-01-01").unwrap().naive_local().to_string().replace(' ', "T"));
+        assert!(parse_date_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(90, parse_duration("90").unwrap());
+        assert_eq!(5400, parse_duration("1h30m").unwrap());
+        assert_eq!(2700, parse_duration("45m").unwrap());
+        assert_eq!(3837, parse_duration("1:03:57").unwrap());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("an hour").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use super::common::errors::{Error, Result};
+use super::item::{Diagnostic, Item};
+use super::registry::Registry;
+use super::skybox::{Listing, SkyBox};
+
+use futures::future::join_all;
+use futures::prelude::*;
+use futures::stream;
+use serde::Serialize;
+
+/**
+ * A recording, and the name of the box holding it
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetItem {
+    #[serde(rename = "Box")]
+    pub skybox: String,
+    #[serde(flatten)]
+    pub item: Item,
+}
+
+/**
+ * Recordings from several boxes, the problems met parsing them, each by box,
+ * and the boxes that could not be listed
+ */
+#[derive(Debug, Default)]
+pub struct FleetListing {
+    pub items: Vec<FleetItem>,
+    pub diagnostics: Vec<(String, Diagnostic)>,
+    pub failures: Vec<(String, Error)>,
+}
+
+impl FleetListing {
+
+    /**
+     * The number of recordings that were fetched, whether parsed or skipped
+     */
+    pub fn fetched(&self) -> usize {
+        self.items.len() + self.diagnostics.iter().filter(|(_, d)| d.skipped).count()
+    }
+}
+
+/**
+ * Several named SkyBoxes, used together.
+ * Each box is called concurrently, and the failure of one does not affect the others.
+ */
+#[derive(Debug)]
+pub struct Fleet {
+    boxes: Vec<(String, SkyBox)>
+}
+
+impl Fleet {
+
+    pub fn new(boxes: Vec<(String, SkyBox)>) -> Fleet {
+        Fleet{boxes}
+    }
+
+    /**
+     * Every box in the registry
+     */
+    pub fn from_registry(registry: &Registry) -> Result<Fleet> {
+        let boxes = registry.boxes.iter()
+            .map(|entry| Ok((entry.name.clone(), entry.skybox()?)))
+            .collect::<Result<_>>()?;
+
+        Ok(Fleet::new(boxes))
+    }
+
+    /**
+     * Configure every box the same way, e.g. with a page size or sort
+     */
+    pub fn configure<F>(self, configure: F) -> Result<Fleet>
+        where F: Fn(SkyBox) -> Result<SkyBox>
+    {
+        let boxes = self.boxes.into_iter()
+            .map(|(name, skybox)| Ok((name, configure(skybox)?)))
+            .collect::<Result<_>>()?;
+
+        Ok(Fleet::new(boxes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /**
+     * List the recordings of every box, each with the name of its box
+     */
+    pub async fn list_items(&self) -> FleetListing {
+        self.list_items_with_progress(|_, _, _| ()).await
+    }
+
+    /**
+     * List the recordings of every box, calling `progress` with a box name, the count fetched from it so far and its total
+     */
+    pub async fn list_items_with_progress<F>(&self, progress: F) -> FleetListing
+        where F: Fn(&str, usize, usize)
+    {
+        let progress = &progress;
+        let listings = join_all(self.boxes.iter().map(|(name, skybox)|
+            skybox.list_items_with_progress(move |fetched, total| progress(name, fetched, total))
+        )).await;
+
+        let mut result = FleetListing::default();
+        for ((name, _), listing) in self.boxes.iter().zip(listings) {
+            match listing {
+                Ok(listing) => {
+                    result.items.extend(listing.items.into_iter().map(|item| FleetItem { skybox: name.clone(), item }));
+                    result.diagnostics.extend(listing.diagnostics.into_iter().map(|diagnostic| (name.clone(), diagnostic)));
+                }
+                Err(error) => result.failures.push((name.clone(), error))
+            }
+        }
+
+        result
+    }
+
+    /**
+     * Every page of recordings from every box, as `SkyBox::pages` gives them, each with the name of its box.
+     * Pages from different boxes are interleaved as they arrive; a box that fails ends with its error, and does not stop the others.
+     */
+    pub fn pages(&self) -> impl Stream<Item = (&str, Result<(Listing, usize)>)> + '_ {
+        stream::select_all(self.boxes.iter().map(|(name, skybox)|
+            Box::pin(skybox.pages().map(move |page| (name.as_str(), page)))
+        ))
+    }
+
+    /**
+     * Try to remove a recording from every box, giving the outcome for each
+     */
+    pub async fn remove_item(&self, item_id: &str) -> Vec<(String, Result<()>)> {
+        let outcomes = join_all(self.boxes.iter().map(|(_, skybox)| skybox.remove_item(item_id))).await;
+
+        self.boxes.iter()
+            .map(|(name, _)| name.clone())
+            .zip(outcomes)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::errors::ErrorKind;
+    use crate::simulator::{Simulator, RECORDINGS, switched_off};
+
+    fn fleet(boxes: Vec<(&str, SkyBox)>) -> Fleet {
+        Fleet::new(boxes.into_iter().map(|(name, skybox)| (name.to_owned(), skybox)).collect())
+    }
+
+    // Two boxes holding the same recordings, but for the ID of the first
+    async fn lounge_and_bedroom() -> (Simulator, Simulator) {
+        let lounge = Simulator::start(RECORDINGS).await.unwrap();
+        let bedroom = Simulator::start(&RECORDINGS.replacen("BOOK:687878212", "BOOK:1", 1)).await.unwrap();
+        (lounge, bedroom)
+    }
+
+    #[tokio::test]
+    async fn test_list_items() {
+        let (lounge, bedroom) = lounge_and_bedroom().await;
+        let fleet = fleet(vec![
+            ("lounge", lounge.skybox()),
+            ("attic", switched_off()),
+            ("bedroom", bedroom.skybox()),
+        ]);
+
+        let result = fleet.list_items().await;
+
+        assert_eq!(8, result.items.len());
+        assert_eq!("lounge", result.items[0].skybox);
+        assert_eq!("bedroom", result.items[4].skybox);
+        assert_eq!("BOOK:1", result.items[4].item.id);
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(8, result.fetched());
+
+        assert_eq!(vec!["attic"], result.failures.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_pages() {
+        let lounge = Simulator::start(RECORDINGS).await.unwrap();
+        let fleet = fleet(vec![
+            ("lounge", lounge.skybox()),
+            ("attic", switched_off()),
+        ]).configure(|skybox| Ok(skybox.with_page_size(3))).unwrap();
+
+        let pages: Vec<_> = fleet.pages().collect().await;
+
+        let lounge_pages: Vec<_> = pages.iter()
+            .filter(|(name, _)| *name == "lounge")
+            .map(|(_, page)| page.as_ref().unwrap().0.items.len())
+            .collect();
+        assert_eq!(vec![3, 1], lounge_pages);
+        assert!(pages.iter().any(|(name, page)| *name == "attic" && page.is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_list_items_diagnostics() {
+        let lounge = Simulator::start(RECORDINGS).await.unwrap();
+        let bedroom = Simulator::start(&RECORDINGS.replacen("<dc:title>Match of the Day</dc:title>", "", 1)).await.unwrap();
+        let fleet = fleet(vec![
+            ("lounge", lounge.skybox()),
+            ("bedroom", bedroom.skybox()),
+        ]);
+
+        let result = fleet.list_items().await;
+
+        assert_eq!(7, result.items.len());
+        assert_eq!(1, result.diagnostics.len());
+        assert_eq!("bedroom", result.diagnostics[0].0);
+        assert_eq!(Some("BOOK:688555858"), result.diagnostics[0].1.item_id.as_deref());
+        assert_eq!(8, result.fetched());
+
+        let json = serde_json::to_value(&result.items[0]).unwrap();
+        assert_eq!("lounge", json["Box"]);
+        assert_eq!("BOOK:687878212", json["Id"]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_item() {
+        let (lounge, bedroom) = lounge_and_bedroom().await;
+        let fleet = fleet(vec![
+            ("lounge", lounge.skybox()),
+            ("bedroom", bedroom.skybox()),
+        ]);
+
+        let outcomes = fleet.remove_item("BOOK:1").await;
+
+        match outcomes[0] {
+            (ref name, Err(ref error)) if name == "lounge" => match error.kind() {
+                ErrorKind::NoSuchObject(701, _) => (),
+                kind => panic!("Unexpected {:?}", kind)
+            },
+            ref outcome => panic!("Unexpected {:?}", outcome)
+        }
+        assert_eq!("bedroom", outcomes[1].0);
+        assert!(outcomes[1].1.is_ok());
+        assert_eq!(3, bedroom.recording_ids().len());
+        assert_eq!(4, lounge.recording_ids().len());
+    }
+}
@@ -2,6 +2,8 @@ use super::common::errors::Result;
 
 use serde::Serialize;
 use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 use lazy_static::lazy_static;
 use num_traits::FromPrimitive;
@@ -14,18 +16,33 @@ pub struct Item {
     pub res: String,
 
     pub title: String,
-    pub description: String,
+    pub description: Option<String>,
     pub viewed: bool,
 
     pub recorded_starttime: DateTime<FixedOffset>,
     pub recorded_duration: u64, //Seconds
 
     pub channel_name: String,
+    pub channel_number: Option<u32>,
+    pub channel_id: Option<String>,
+    pub program_id: Option<String>,
     pub series_id: Option<String>,
     pub service_type: ServiceType,
+
+    pub scheduled_starttime: Option<DateTime<FixedOffset>>,
+    pub scheduled_endtime: Option<DateTime<FixedOffset>>,
+
+    pub resource: Option<Resource>,
+    pub flags: Option<Flags>,
+    pub booking: Option<Booking>,
+    pub recording_status: Option<RecordingStatus>,
+
+    pub last_playback_position: Option<u64>,
+    pub last_viewed_time: Option<DateTime<FixedOffset>>,
+    pub estimated_bit_rate: Option<u64>, //Bits per second
 }
 
-#[derive(Debug, Serialize, FromPrimitive, Clone, PartialEq)]
+#[derive(Debug, Serialize, FromPrimitive, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum ServiceType {
     Music = 16,
@@ -40,8 +57,81 @@ pub enum ServiceType {
     Unknown = 0
 }
 
+/**
+ * Attributes of the `res` element
+ */
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Resource {
+    pub size: u64, //Bytes
+    pub duration: u64, //Seconds
+    pub protocol_info: String,
+}
+
+/**
+ * Attributes of the `X_flags` element
+ */
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Flags {
+    pub hd: bool,
+    pub uhd: bool,
+    pub widescreen: bool,
+    pub has_audio_desc: bool,
+    pub has_foreign_subtitles: bool,
+    pub is_adult: bool,
+    pub is_3d: bool,
+}
+
+/**
+ * The `X_bookingKeep` and `X_bookingLock` elements
+ */
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Booking {
+    pub keep: bool,
+    pub lock: bool,
+}
+
+/**
+ * The `X_recStatus` element and its attributes
+ */
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordingStatus {
+    pub status: u32,
+    pub failed: bool,
+    pub content_status: u32,
+    pub exception: u32,
+    pub rec_state: u32,
+}
+
+impl FromStr for ServiceType {
+    type Err = super::common::errors::Error;
+
+    /**
+     * Parse a genre name, ignoring case, e.g. `documentary`
+     */
+    fn from_str(name: &str) -> Result<ServiceType> {
+        let service_type = match name.to_ascii_lowercase().as_str() {
+            "music" => ServiceType::Music,
+            "documentary" => ServiceType::Documentary,
+            "lifestyle" => ServiceType::Lifestyle,
+            "sport" => ServiceType::Sport,
+            "movies" => ServiceType::Movies,
+            "news" => ServiceType::News,
+            "entertainment" => ServiceType::Entertainment,
+            "kids" => ServiceType::Kids,
+            "unknown" => ServiceType::Unknown,
+            _ => return Err(format!("Unknown genre: {}", name).into())
+        };
+        Ok(service_type)
+    }
+}
+
 lazy_static! {
     static ref DURATION_RE: Regex = Regex::new(r"P0D(\d+):(\d+):(\d+)").expect("Cannot compile regex!");
+    static ref CLOCK_RE: Regex = Regex::new(r"^(\d+):(\d+):(\d+)$").expect("Cannot compile regex!");
 }
 
 fn string_of_element(elem: &roxmltree::Node, name: &str) -> Result<String> {
@@ -53,9 +143,55 @@ fn string_of_element(elem: &roxmltree::Node, name: &str) -> Result<String> {
     Ok(result)
 }
 
+fn optional_string_of_element(elem: &roxmltree::Node, name: &str) -> Option<String> {
+    elem.children()
+        .find(|e| e.tag_name().name() == name)
+        .and_then(|node| node.text())
+        .map(String::from)
+}
+
+fn child_element<'a, 'input>(elem: &roxmltree::Node<'a, 'input>, name: &str) -> Result<roxmltree::Node<'a, 'input>> {
+    Ok(elem.children()
+        .find(|e| e.tag_name().name() == name)
+        .ok_or(format!("Element `{}` is absent", name))?)
+}
+
+fn parse_element<T>(elem: &roxmltree::Node, name: &str) -> Result<T>
+    where T: FromStr, super::common::errors::Error: From<T::Err>
+{
+    Ok(string_of_element(elem, name)?.parse()?)
+}
+
+fn date_time_of_element(elem: &roxmltree::Node, name: &str) -> Result<DateTime<FixedOffset>> {
+    Ok(DateTime::parse_from_rfc3339(&string_of_element(elem, name)?)?)
+}
+
+fn optional_date_time_of_element(elem: &roxmltree::Node, name: &str) -> Result<Option<DateTime<FixedOffset>>> {
+    match elem.children().find(|e| e.tag_name().name() == name) {
+        Some(_) => Ok(Some(date_time_of_element(elem, name)?)),
+        None => Ok(None)
+    }
+}
+
+fn attribute<'a>(elem: &roxmltree::Node<'a, '_>, name: &str) -> Result<&'a str> {
+    Ok(elem.attribute(name)
+        .ok_or(format!("Attribute `{}` of `{}` is absent", name, elem.tag_name().name()))?)
+}
+
+fn parse_attribute<T>(elem: &roxmltree::Node, name: &str) -> Result<T>
+    where T: FromStr, super::common::errors::Error: From<T::Err>
+{
+    Ok(attribute(elem, name)?.parse()?)
+}
+
+fn bool_attribute(elem: &roxmltree::Node, name: &str) -> Result<bool> {
+    Ok(parse_attribute::<u8>(elem, name)? == 1)
+}
+
 fn parse_duration(duration: &str) -> Result<Duration> {
     let caps = DURATION_RE
         .captures(duration)
+        .or_else(|| CLOCK_RE.captures(duration))
         .ok_or(format!("Cannot parse duration: {}", duration))?;
 
     let hours: u32 = caps.get(1).ok_or("Hours field is absent")?.as_str().parse()?;
@@ -66,36 +202,200 @@ fn parse_duration(duration: &str) -> Result<Duration> {
 }
 
 
+/**
+ * A problem parsing one recording: either an optional field left out of its `Item`,
+ * or the reason the whole recording was skipped
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub item_id: Option<String>,
+    pub message: String,
+    pub skipped: bool,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let item_id = self.item_id.as_deref().unwrap_or("<unknown>");
+        if self.skipped {
+            write!(f, "skipped {}: {}", item_id, self.message)
+        } else {
+            write!(f, "{}: {}", item_id, self.message)
+        }
+    }
+}
+
+/**
+ * Collects the problems with optional fields
+ */
+#[derive(Default)]
+struct Problems {
+    messages: Vec<String>
+}
+
+impl Problems {
+    fn optional<T>(&mut self, result: Result<T>) -> Option<T> {
+        result.map_err(|error| self.messages.push(error.to_string())).ok()
+    }
+}
+
 impl Item {
 
+    /**
+     * The properties `Item` is parsed from, for the `Filter` argument of `Browse`
+     */
+    pub const PROPERTIES: &'static [&'static str] = &[
+        "res", "res@size", "res@duration", "res@protocolInfo",
+        "dc:title", "dc:description",
+        "upnp:channelName", "upnp:channelNr", "upnp:channelID", "upnp:programID", "upnp:seriesID",
+        "upnp:recordedStartDateTime", "upnp:recordedDuration", "upnp:scheduledStartTime", "upnp:scheduledEndTime",
+        "vx:X_genre", "vx:X_isViewed", "vx:X_lastPlaybackPosition", "vx:X_lastViewedTime", "vx:X_estimatedBitRate",
+        "vx:X_flags", "vx:X_flags@hd", "vx:X_flags@uhd", "vx:X_flags@widescreen", "vx:X_flags@hasAudioDesc",
+        "vx:X_flags@hasForeignSubtitles", "vx:X_flags@isAdult", "vx:X_flags@is3D",
+        "vx:X_bookingKeep", "vx:X_bookingLock",
+        "vx:X_recStatus", "vx:X_recStatus@failed", "vx:X_recStatus@contentStatus", "vx:X_recStatus@exception",
+        "vx:X_recStatus@recState",
+    ];
+
+    /**
+     * Parse an `<item>` element, failing on an absent or malformed required field, and leaving out any optional one
+     */
     pub fn build(elem: roxmltree::Node) -> Result<Item> {
+        Item::parse(&elem, &mut Problems::default())
+    }
 
-        let recorded_duration = string_of_element(&elem, "recordedDuration")?;
-        let recorded_duration = parse_duration(recorded_duration.as_str())?.as_secs();
+    /**
+     * Parse an `<item>` element, failing on any absent or malformed field, optional or not
+     */
+    pub fn build_strict(elem: roxmltree::Node) -> Result<Item> {
+        let mut diagnostics = Vec::new();
+        let item = Item::build_lenient(elem, &mut diagnostics);
+
+        match diagnostics.first() {
+            Some(diagnostic) => Err(diagnostic.to_string().into()),
+            None => item.ok_or_else(|| "Cannot parse item".into())
+        }
+    }
+
+    /**
+     * Parse an `<item>` element, leaving out any absent or malformed optional field.
+     * Each problem is added to `diagnostics`, and `None` is returned if a required field is absent or malformed.
+     */
+    pub fn build_lenient(elem: roxmltree::Node, diagnostics: &mut Vec<Diagnostic>) -> Option<Item> {
+        let item_id = elem.attribute("id").map(String::from);
+        let mut problems = Problems::default();
+
+        match Item::parse(&elem, &mut problems) {
+            Ok(item) => {
+                diagnostics.extend(problems.messages.into_iter().map(|message| Diagnostic {
+                    item_id: item_id.clone(), message, skipped: false
+                }));
+                Some(item)
+            },
+            Err(error) => {
+                diagnostics.push(Diagnostic { item_id, message: error.to_string(), skipped: true });
+                None
+            }
+        }
+    }
 
-        let recorded_starttime = DateTime::parse_from_rfc3339(&string_of_element(&elem, "recordedStartDateTime")?)?;
-        let id = elem.attribute("id").ok_or("Field `id` is absent")?.into();
-        let title = string_of_element(&elem, "title")?;
-        let description = string_of_element(&elem, "description")?;
-        let channel_name = string_of_element(&elem, "channelName")?;
-        let res = string_of_element(&elem, "res")?;
-        let service_type: i32 = string_of_element(&elem, "X_genre")?.parse()?;
+    fn parse(elem: &roxmltree::Node, problems: &mut Problems) -> Result<Item> {
 
-        let service_type = FromPrimitive::from_i32(service_type)
+        // Required fields
+        let id = elem.attribute("id").ok_or("Attribute `id` is absent")?.into();
+        let res = string_of_element(elem, "res")?;
+        let title = string_of_element(elem, "title")?;
+        let channel_name = string_of_element(elem, "channelName")?;
+
+        let recorded_starttime = date_time_of_element(elem, "recordedStartDateTime")?;
+        let recorded_duration = string_of_element(elem, "recordedDuration")?;
+        let recorded_duration = parse_duration(recorded_duration.as_str())?.as_secs();
+
+        // Fields that may be legitimately absent or empty
+        let description = optional_string_of_element(elem, "description");
+        let series_id = optional_string_of_element(elem, "seriesID");
+        let scheduled_endtime = problems.optional(optional_date_time_of_element(elem, "scheduledEndTime")).flatten();
+        let last_viewed_time = problems.optional(optional_date_time_of_element(elem, "X_lastViewedTime")).flatten();
+
+        // Fields that should be present
+        let viewed = problems.optional(string_of_element(elem, "X_isViewed"))
+            .as_deref() == Some("1");
+        let service_type = problems.optional(parse_element(elem, "X_genre"))
+            .and_then(FromPrimitive::from_i32)
             .unwrap_or(ServiceType::Unknown);
 
-        let viewed = "1" == string_of_element(&elem, "X_isViewed")?;
-        let series_id = elem.children()
-            .find(|e| e.tag_name().name() == "seriesID")
-            .and_then(|node| node.text()).map(String::from);
+        let channel_number = problems.optional(parse_element(elem, "channelNr"));
+        let channel_id = problems.optional(string_of_element(elem, "channelID"));
+        let program_id = problems.optional(string_of_element(elem, "programID"));
+        let scheduled_starttime = problems.optional(date_time_of_element(elem, "scheduledStartTime"));
+
+        let resource = problems.optional(child_element(elem, "res").and_then(|e| Resource::build(&e)));
+        let flags = problems.optional(child_element(elem, "X_flags").and_then(|e| Flags::build(&e)));
+        let booking = problems.optional(Booking::build(elem));
+        let recording_status = problems.optional(child_element(elem, "X_recStatus").and_then(|e| RecordingStatus::build(&e)));
+
+        let last_playback_position = problems.optional(parse_element(elem, "X_lastPlaybackPosition"));
+        let estimated_bit_rate = problems.optional(parse_element(elem, "X_estimatedBitRate"));
+
+        Ok(Item {
+            id, res, title, description, viewed,
+            recorded_starttime, recorded_duration,
+            channel_name, channel_number, channel_id, program_id, series_id, service_type,
+            scheduled_starttime, scheduled_endtime,
+            resource, flags, booking, recording_status,
+            last_playback_position, last_viewed_time, estimated_bit_rate
+        })
+    }
+}
+
+impl Resource {
+    fn build(elem: &roxmltree::Node) -> Result<Resource> {
+        Ok(Resource {
+            size: parse_attribute(elem, "size")?,
+            duration: parse_duration(attribute(elem, "duration")?)?.as_secs(),
+            protocol_info: attribute(elem, "protocolInfo")?.into(),
+        })
+    }
+}
 
-        Ok(Item { id, res, title, description, viewed, recorded_starttime, recorded_duration, channel_name, series_id, service_type })
+impl Booking {
+    fn build(elem: &roxmltree::Node) -> Result<Booking> {
+        Ok(Booking {
+            keep: "1" == string_of_element(elem, "X_bookingKeep")?,
+            lock: "1" == string_of_element(elem, "X_bookingLock")?,
+        })
+    }
+}
+
+impl Flags {
+    fn build(elem: &roxmltree::Node) -> Result<Flags> {
+        Ok(Flags {
+            hd: bool_attribute(elem, "hd")?,
+            uhd: bool_attribute(elem, "uhd")?,
+            widescreen: bool_attribute(elem, "widescreen")?,
+            has_audio_desc: bool_attribute(elem, "hasAudioDesc")?,
+            has_foreign_subtitles: bool_attribute(elem, "hasForeignSubtitles")?,
+            is_adult: bool_attribute(elem, "isAdult")?,
+            is_3d: bool_attribute(elem, "is3D")?,
+        })
+    }
+}
+
+impl RecordingStatus {
+    fn build(elem: &roxmltree::Node) -> Result<RecordingStatus> {
+        Ok(RecordingStatus {
+            status: elem.text().ok_or("Element `X_recStatus` is empty")?.parse()?,
+            failed: bool_attribute(elem, "failed")?,
+            content_status: parse_attribute(elem, "contentStatus")?,
+            exception: parse_attribute(elem, "exception")?,
+            rec_state: parse_attribute(elem, "recState")?,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::simulator::{RECORDINGS, first_item, with_first_item};
 
     #[test]
     fn test_item_build_fails() {
@@ -212,8 +512,8 @@ mod tests {
             assert_eq!(item.res, "file://pvr/29003044");
 
             assert_eq!(item.title, "Ewan McGregor: Cold Chain Mission");
-            assert_eq!(item.description, "1/2. Ewan McGregor is on a mission to immunise some of the hardest-to-reach children in the world. He starts in India and then continues to Nepal. Contains some strong language.  Also in HD. [AD,S]");
-            assert_eq!(item.viewed, true);
+            assert_eq!(item.description.unwrap(), "1/2. Ewan McGregor is on a mission to immunise some of the hardest-to-reach children in the world. He starts in India and then continues to Nepal. Contains some strong language.  Also in HD. [AD,S]");
+            assert!(item.viewed);
 
             assert_eq!(item.recorded_starttime, DateTime::parse_from_rfc3339("2012-04-22T20:58:02+01:00").unwrap());
             assert_eq!(item.recorded_duration, 3837);
@@ -221,6 +521,82 @@ mod tests {
             assert_eq!(item.channel_name, "BBC 2 England");
             assert_eq!(item.series_id, Some("13369".into()));
             assert_eq!(item.service_type, ServiceType::Documentary);
+
+            assert_eq!(item.channel_number, Some(102));
+            assert_eq!(item.channel_id, Some("xsi://7D6".into()));
+            assert_eq!(item.program_id, Some("xsi://7D6;B1C9".into()));
+            assert_eq!(item.scheduled_starttime, Some(DateTime::parse_from_rfc3339("2012-04-22T21:00:00+01:00").unwrap()));
+            assert_eq!(item.scheduled_endtime, Some(DateTime::parse_from_rfc3339("2012-04-22T21:00:00Z").unwrap()));
+
+            assert_eq!(item.resource, Some(Resource { size: 1957124932, duration: 3837, protocol_info: "internal:192.168.59.177:*:*".into() }));
+            assert_eq!(item.flags, Some(Flags {
+                hd: false, uhd: false, widescreen: true, has_audio_desc: true, has_foreign_subtitles: true, is_adult: false, is_3d: false
+            }));
+            assert_eq!(item.booking, Some(Booking { keep: false, lock: false }));
+            assert_eq!(item.recording_status, Some(RecordingStatus { status: 5, failed: false, content_status: 3, exception: 100, rec_state: 7 }));
+
+            assert_eq!(item.last_playback_position, Some(0));
+            assert_eq!(item.last_viewed_time, Some(DateTime::parse_from_rfc3339("2021-05-25T21:06:04Z").unwrap()));
+            assert_eq!(item.estimated_bit_rate, Some(5767168));
        }
 
+    #[test]
+    fn test_item_serialises_to_json() {
+        let item = first_item();
+
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(json["ChannelNumber"], 102);
+        assert_eq!(json["Resource"]["Size"], 1957124932u64);
+        assert_eq!(json["Flags"]["HasAudioDesc"], true);
+        assert_eq!(json["RecordingStatus"]["RecState"], 7);
+        assert_eq!(json["LastViewedTime"], "2021-05-25T21:06:04Z");
+    }
+
+    #[test]
+    fn test_item_build_lenient() {
+        let didl_lite = RECORDINGS
+            .replacen("dc:description>", "dc:comment>", 2)
+            .replacen("<vx:X_flags ", "<vx:X_oldFlags ", 1)
+            .replacen("<vx:X_estimatedBitRate>5767168", "<vx:X_estimatedBitRate>fast", 1);
+
+        let mut diagnostics = Vec::new();
+        let item = with_first_item(&didl_lite, |elem| Item::build_lenient(elem, &mut diagnostics)).unwrap();
+
+        assert_eq!(None, item.description);
+        assert_eq!(None, item.flags);
+        assert_eq!(None, item.estimated_bit_rate);
+        assert_eq!(Some(102), item.channel_number);
+
+        assert_eq!(2, diagnostics.len());
+        assert_eq!(Diagnostic {
+            item_id: Some("BOOK:687878212".into()),
+            message: "Element `X_flags` is absent".into(),
+            skipped: false
+        }, diagnostics[0]);
+        assert!(diagnostics.iter().all(|d| !d.skipped));
+
+        assert_eq!(None, with_first_item(&didl_lite, Item::build).unwrap().flags);
+        assert!(with_first_item(&didl_lite, Item::build_strict).is_err());
+    }
+
+    #[test]
+    fn test_item_build_lenient_skips() {
+        let didl_lite = RECORDINGS
+            .replacen("<dc:title>Ewan McGregor: Cold Chain Mission</dc:title>", "", 1);
+
+        let mut diagnostics = Vec::new();
+        assert!(with_first_item(&didl_lite, |elem| Item::build_lenient(elem, &mut diagnostics)).is_none());
+
+        assert_eq!(1, diagnostics.len());
+        assert!(diagnostics[0].skipped);
+        assert_eq!("skipped BOOK:687878212: Element `title` is absent", diagnostics[0].to_string());
+    }
+
+    #[test]
+    fn test_service_type_from_str() {
+        assert_eq!(ServiceType::Documentary, "Documentary".parse().unwrap());
+        assert_eq!(ServiceType::Kids, "kids".parse().unwrap());
+        assert!("Soap".parse::<ServiceType>().is_err());
+    }
+
 }
\ No newline at end of file
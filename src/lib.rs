@@ -0,0 +1,35 @@
+//! Interact with Sky Plus hardware: discover boxes on the LAN, list, remove and play recordings.
+
+#[macro_use]
+extern crate num_derive;
+#[macro_use]
+extern crate error_chain;
+
+mod common;
+mod content;
+mod device;
+mod filter;
+mod fleet;
+mod item;
+mod registry;
+mod skybox;
+pub mod soap;
+mod scanner;
+mod sort;
+mod ssdp;
+mod watcher;
+#[cfg(test)]
+mod simulator;
+
+pub use common::{SKY_BROWSE, SKY_PLAY};
+pub use common::errors::{Error, ErrorKind, Result, ResultExt};
+pub use content::{ContentObject, RECORDINGS_CONTAINER, ROOT_CONTAINER};
+pub use device::DeviceInfo;
+pub use filter::{Filter, parse_date_time, parse_duration};
+pub use fleet::{Fleet, FleetItem, FleetListing};
+pub use item::{Booking, Diagnostic, Flags, Item, RecordingStatus, Resource, ServiceType};
+pub use registry::{BoxEntry, Registry};
+pub use scanner::{ScanDiagnostic, ScanReport, Scanner, Selection};
+pub use skybox::{Listing, SkyBox};
+pub use sort::{Sort, SortField, SortKey};
+pub use watcher::{Change, NetworkTable, Notification, Sighting, Watcher};
@@ -1,124 +1,231 @@
-use super::item::Item;
-use indicatif::ProgressBar;
+use skybox::{FleetItem, Item, ServiceType};
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::io::{self, Write};
 
 pub trait Lister {
-    fn list(&mut self, items: &[Item]);
-    fn close(&mut self);
+    fn row(&mut self, skybox: Option<&str>, item: &Item) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    fn close(&mut self) -> io::Result<()>;
+
+    fn list(&mut self, items: &[Item]) -> io::Result<()> {
+        for item in items.iter() {
+            self.row(None, item)?;
+        }
+        self.flush()
+    }
+
+    fn list_fleet(&mut self, items: &[FleetItem]) -> io::Result<()> {
+        for fleet_item in items.iter() {
+            self.row(Some(&fleet_item.skybox), &fleet_item.item)?;
+        }
+        self.flush()
+    }
 }
 
 /**
  * Factory: build a lister
  */
-pub fn build_lister(item_count: usize, matches: &clap::ArgMatches) -> impl Lister {
-    ProgressLister::new(item_count, match matches.value_of("FORMAT") {
-        Some("JSON") => Box::new(JSONLister::new(item_count)) as Box<dyn Lister>,
-        Some("CSV") => Box::new(CSVLister::new(item_count)) as Box<dyn Lister>,
-        _ => Box::new(SimpleLister::new(item_count, matches.clone()))
-    })
+pub fn build_lister(item_count: usize, matches: &clap::ArgMatches) -> Box<dyn Lister> {
+    match matches.value_of("FORMAT") {
+        Some("JSON") => Box::new(JSONLister::new(item_count)),
+        Some("CSV") => Box::new(CSVLister::new()),
+        Some("NDJSON") => Box::new(NDJSONLister{}),
+        _ => Box::new(SimpleLister::new(item_count))
+    }
 }
 
 
 //===========
 
 /**
- * Wrap another Lister and drive a progress bar
+ * Output Items as CSV, as they are listed
  */
-struct ProgressLister {
-    start: std::time::Instant,
-    progress: ProgressBar,
-    item_count: usize,
-
-    inner: Box<dyn Lister>
+struct CSVLister {
+    writer: csv::Writer<io::Stdout>
 }
 
-impl ProgressLister {
-    fn new(item_count: usize, inner: Box<dyn Lister>) -> Self {
-        let prog = ProgressBar::new(item_count as u64);
-        prog.println("Fetching recordings from skybox");
+impl CSVLister {
 
-        ProgressLister {
-            start: std::time::Instant::now(),
-            progress: prog,
-            item_count,
-            inner
+    fn new() -> Self {
+        CSVLister{
+            writer: csv::Writer::from_writer(io::stdout())
         }
     }
 
 }
 
-impl Lister for ProgressLister {
+impl Lister for CSVLister {
 
-    fn list(&mut self, items: &[Item]) {
-        self.progress.inc(items.len() as u64);
-        self.inner.list(items);
+    fn row(&mut self, skybox: Option<&str>, item: &Item) -> io::Result<()> {
+        Ok(self.writer.serialize(CSVRecord::from((skybox, item)))?)
     }
-    fn close(&mut self) {
-        self.inner.close();
 
-        let msg = format!("Fetched {} items in {}s", self.item_count, self.start.elapsed().as_secs());
-        self.progress.finish_with_message(&msg);
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
     }
 
-}
+    fn close(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
 
-//===========
+}
 
 /**
- * Output Items as CSV
+ * An Item flattened into one row, as CSV cannot nest structures
  */
-struct CSVLister {
-    items: Vec<Item>,
-    writer: csv::Writer<std::io::Stdout>
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CSVRecord<'a> {
+    #[serde(rename = "Box", skip_serializing_if = "Option::is_none")]
+    skybox: Option<&'a str>,
+    id: &'a str,
+    res: &'a str,
+
+    title: &'a str,
+    description: Option<&'a str>,
+    viewed: bool,
+
+    recorded_starttime: &'a DateTime<FixedOffset>,
+    recorded_duration: u64,
+
+    channel_name: &'a str,
+    channel_number: Option<u32>,
+    channel_id: Option<&'a str>,
+    program_id: Option<&'a str>,
+    series_id: Option<&'a str>,
+    service_type: &'a ServiceType,
+
+    scheduled_starttime: Option<&'a DateTime<FixedOffset>>,
+    scheduled_endtime: Option<&'a DateTime<FixedOffset>>,
+
+    size: Option<u64>,
+    duration: Option<u64>,
+    protocol_info: Option<&'a str>,
+
+    hd: Option<bool>,
+    uhd: Option<bool>,
+    widescreen: Option<bool>,
+    has_audio_desc: Option<bool>,
+    has_foreign_subtitles: Option<bool>,
+    is_adult: Option<bool>,
+    is_3d: Option<bool>,
+
+    keep: Option<bool>,
+    lock: Option<bool>,
+
+    rec_status: Option<u32>,
+    rec_failed: Option<bool>,
+    content_status: Option<u32>,
+    exception: Option<u32>,
+    rec_state: Option<u32>,
+
+    last_playback_position: Option<u64>,
+    last_viewed_time: Option<&'a DateTime<FixedOffset>>,
+    estimated_bit_rate: Option<u64>,
 }
 
-impl CSVLister {
-
-    fn new(item_count: usize) -> Self {
-        CSVLister{
-            items: Vec::with_capacity(item_count),
-            writer: csv::Writer::from_writer(std::io::stdout())
+impl<'a> From<(Option<&'a str>, &'a Item)> for CSVRecord<'a> {
+    fn from((skybox, item): (Option<&'a str>, &'a Item)) -> Self {
+        let resource = item.resource.as_ref();
+        let flags = item.flags.as_ref();
+        let status = item.recording_status.as_ref();
+
+        CSVRecord {
+            skybox,
+            id: &item.id,
+            res: &item.res,
+            title: &item.title,
+            description: item.description.as_deref(),
+            viewed: item.viewed,
+            recorded_starttime: &item.recorded_starttime,
+            recorded_duration: item.recorded_duration,
+            channel_name: &item.channel_name,
+            channel_number: item.channel_number,
+            channel_id: item.channel_id.as_deref(),
+            program_id: item.program_id.as_deref(),
+            series_id: item.series_id.as_deref(),
+            service_type: &item.service_type,
+            scheduled_starttime: item.scheduled_starttime.as_ref(),
+            scheduled_endtime: item.scheduled_endtime.as_ref(),
+            size: resource.map(|r| r.size),
+            duration: resource.map(|r| r.duration),
+            protocol_info: resource.map(|r| r.protocol_info.as_str()),
+            hd: flags.map(|f| f.hd),
+            uhd: flags.map(|f| f.uhd),
+            widescreen: flags.map(|f| f.widescreen),
+            has_audio_desc: flags.map(|f| f.has_audio_desc),
+            has_foreign_subtitles: flags.map(|f| f.has_foreign_subtitles),
+            is_adult: flags.map(|f| f.is_adult),
+            is_3d: flags.map(|f| f.is_3d),
+            keep: item.booking.as_ref().map(|b| b.keep),
+            lock: item.booking.as_ref().map(|b| b.lock),
+            rec_status: status.map(|s| s.status),
+            rec_failed: status.map(|s| s.failed),
+            content_status: status.map(|s| s.content_status),
+            exception: status.map(|s| s.exception),
+            rec_state: status.map(|s| s.rec_state),
+            last_playback_position: item.last_playback_position,
+            last_viewed_time: item.last_viewed_time.as_ref(),
+            estimated_bit_rate: item.estimated_bit_rate,
         }
     }
-
 }
 
-impl Lister for CSVLister {
-
-    fn list(&mut self, items: &[Item]) {
-        self.items.extend_from_slice(items);
-    }
-
-    fn close(&mut self) {
-        for item in self.items.iter() {
-            self.writer.serialize(item).unwrap();
-        }
-    }
+/**
+ * An Item as a JSON object, with the name of its box when listing several
+ */
+#[derive(Serialize)]
+struct JSONRecord<'a> {
+    #[serde(rename = "Box", skip_serializing_if = "Option::is_none")]
+    skybox: Option<&'a str>,
+    #[serde(flatten)]
+    item: &'a Item,
+}
 
+fn to_json(skybox: Option<&str>, item: &Item) -> String {
+    serde_json::to_string(&JSONRecord { skybox, item }).expect("Cannot serialise result")
 }
 
 /**
  * Output Items as JSON
  */
-
 struct JSONLister {
-    items: Vec<Item>
+    rows: Vec<String>
 }
 
 impl JSONLister {
     fn new(item_count: usize) -> Self {
         JSONLister{
-            items: Vec::with_capacity(item_count),
+            rows: Vec::with_capacity(item_count),
         }
     }
 }
 
 impl Lister for JSONLister {
-    fn list(&mut self, items: &[Item]) {
-        self.items.extend_from_slice(items);
+    fn row(&mut self, skybox: Option<&str>, item: &Item) -> io::Result<()> {
+        self.rows.push(to_json(skybox, item));
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        writeln!(io::stdout(), "[{}]", self.rows.join(","))
     }
+}
+
+/**
+ * Output Items as newline delimited JSON, one object per line, as they are listed
+ */
+struct NDJSONLister {}
 
-    fn close(&mut self) {
-        println!("{}", serde_json::to_string(&self.items).expect("Cannot serialise result"));
+impl Lister for NDJSONLister {
+    fn row(&mut self, skybox: Option<&str>, item: &Item) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", to_json(skybox, item))
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
@@ -126,41 +233,86 @@ impl Lister for JSONLister {
  * Output Items a text
  */
 struct SimpleLister {
-    items: Vec<Item>,
-    matches: clap::ArgMatches
+    lines: Vec<String>
 }
 
 impl SimpleLister {
-    fn new(item_count: usize, matches: clap::ArgMatches) -> Self {
+    fn new(item_count: usize) -> Self {
         SimpleLister{
-            items: Vec::with_capacity(item_count),
-            matches
+            lines: Vec::with_capacity(item_count)
         }
     }
 }
 
 impl Lister for SimpleLister {
-    fn list(&mut self, items: &[Item]) {
-        self.items.extend_from_slice(items);
+    fn row(&mut self, skybox: Option<&str>, item: &Item) -> io::Result<()> {
+        let prefix = skybox.map(|skybox| format!("[{}] ", skybox)).unwrap_or_default();
+        self.lines.push(format!("{}{} {} {}: {}",
+            prefix,
+            item.recorded_starttime,
+            item.recorded_duration,
+            item.title,
+            item.description.as_deref().unwrap_or_default()
+        ));
+        Ok(())
     }
 
-    fn close(&mut self) {
-
-        if self.matches.is_present("TIME_ORDER") {
-            if self.matches.is_present("REVERSE_TIME") {
-                self.items.sort_by(|a,b| a.recorded_starttime.cmp(&b.recorded_starttime))
-            } else {
-                self.items.sort_by(|a,b| b.recorded_starttime.cmp(&a.recorded_starttime))
-            }
+    fn close(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        for line in self.lines.iter() {
+            writeln!(stdout, "{}", line)?;
         }
+        Ok(())
+    }
+}
 
-        for item in self.items.iter() {
-            println!("{} {} {}: {}",
-                item.recorded_starttime,
-                item.recorded_duration,
-                item.title,
-                item.description
-            );
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::first_item;
+
+    #[test]
+    fn test_csv_record() {
+        let item = first_item();
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(CSVRecord::from((None, &item))).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let mut lines = csv.lines();
+        let header: Vec<_> = lines.next().unwrap().split(',').collect();
+        assert_eq!(Some(&"Id"), header.first());
+        assert!(header.contains(&"ChannelNumber"));
+        assert!(header.contains(&"HasAudioDesc"));
+        assert!(header.contains(&"EstimatedBitRate"));
+
+        let row = lines.next().unwrap();
+        assert!(row.starts_with("BOOK:687878212,file://pvr/29003044,"));
+        assert!(row.contains(",1957124932,3837,"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_csv_record_box() {
+        let item = first_item();
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(CSVRecord::from((Some("lounge"), &item))).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("Box,Id,"));
+        assert!(lines.next().unwrap().starts_with("lounge,BOOK:687878212,"));
+    }
+
+    #[test]
+    fn test_json_record_box() {
+        let item = first_item();
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(Some("lounge"), &item)).unwrap();
+        assert_eq!("lounge", json["Box"]);
+        assert_eq!("BOOK:687878212", json["Id"]);
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(None, &item)).unwrap();
+        assert!(json.get("Box").is_none());
+    }
+}
@@ -1,61 +1,163 @@
-#[macro_use]
-extern crate num_derive;
-#[macro_use]
-extern crate error_chain;
-
 use clap::clap_app;
-mod common;
-mod item;
-mod skybox;
-mod scanner;
-mod lister;
+use indicatif::ProgressBar;
+use chrono::Utc;
+use futures::{StreamExt, TryStreamExt};
+use skybox::{Change, Error, ErrorKind, Filter, Fleet, FleetItem, FleetListing, Item, Listing, Registry, Result, Scanner, Selection, Sighting, SkyBox, Sort, Watcher, ROOT_CONTAINER, parse_date_time, parse_duration};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
 
-use common::errors::Result;
-use scanner::Scanner;
+mod lister;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
+    if let Err(error) = run().await {
+        eprintln!("Error: {}", error);
+        std::process::exit(exit_code(&error));
+    }
+}
+
+/**
+ * Distinct exit codes let scripts tell failures apart
+ */
+fn exit_code(error: &Error) -> i32 {
+    match error.kind() {
+        ErrorKind::NoSuchObject(..) => 3,
+        ErrorKind::Busy(..) => 4,
+        _ => 1
+    }
+}
+
+async fn run() -> Result<()> {
 
     let mut config = clap_app!(skybox =>
         (version: "0.1")
         (about: "Interacts with SkyPlus PVRs")
+        (@arg BOX: --box +takes_value +global "Name of the skybox to use, instead of the default. Also read from $SKYBOX")
         (@subcommand scan =>
             (about: "Scan for SkyPlus machines")
+            (@arg FIRST: --first conflicts_with[INDEX HOST] "Use the first skybox found, without asking")
+            (@arg INDEX: --index +takes_value conflicts_with[HOST] "Use the skybox with this index, without asking")
+            (@arg HOST: --host +takes_value "Use the skybox at this IP address, without asking")
+            (@arg JSON: --json "Print the skyboxes found as JSON")
+            (@arg NAME: --name +takes_value "Name for the skybox chosen")
+            (@arg DEFAULT: --default "Use the skybox chosen by default, not only when it is the first")
+            (@arg TIMEOUT: --timeout +takes_value "Seconds to wait for skyboxes to respond, 5 by default")
+            (@arg MX: --mx +takes_value "Most seconds a skybox should wait before responding, 2 by default")
+            (@arg REPEATS: --repeats +takes_value "Times to send each search, 1 by default")
+            (@arg INTERFACE: --interface +takes_value "IP address of the network interface to search from")
+            (@arg VERBOSE: -v --verbose "Report every device that answered, and why any could not be used")
+            (@arg UNICAST: --unicast +takes_value "Search only this IP address, with an optional port, e.g. 192.168.2.152:1900")
+        )
+        (@subcommand add =>
+            (about: "Add a skybox by its IP address, without scanning")
+            (@arg ADDRESS: +required "IP address of the skybox, with an optional port, e.g. 192.168.2.152:49153")
+            (@arg NAME: --name +takes_value "Name for the skybox")
+            (@arg DEFAULT: --default "Use the skybox by default, not only when it is the first")
+        )
+        // Hyphenated, so not an identifier
+        (subcommand: clap_app!(@app (clap::App::new("watch-network"))
+            (about: "Watch skyboxes announce their arrival and departure on the network")
+            (@arg INTERFACE: --interface +takes_value "IP address of the network interface to listen on")
+            (@arg UPDATE: --update "Update the address of saved skyboxes that move")
+        ))
+        (@subcommand boxes =>
+            (about: "Manage the skyboxes found")
+            (@subcommand list =>
+                (about: "List the skyboxes found, marking the default with `*`")
+            )
+            (@subcommand rename =>
+                (about: "Rename a skybox")
+                (@arg NAME: +required "Current name")
+                (@arg NEW_NAME: +required "New name")
+            )
+            (@subcommand remove =>
+                (about: "Forget a skybox")
+                (@arg NAME: +required "Name of the skybox")
+            )
+            (@subcommand default =>
+                (about: "Use a skybox when none is named")
+                (@arg NAME: +required "Name of the skybox")
+            )
         )
         (@subcommand ls =>
             (about: "list recordings")
             (@arg UNWATCHED: -u "Exclude viewed recordings")
-            (@arg TIME_ORDER: -t "list in time order")
-            (@arg REVERSE_TIME: -r "reverse time order")
-            (@arg FORMAT: -o --output +takes_value "Output: JSON|CSV")
+            (@arg VIEWED: --viewed conflicts_with[UNWATCHED] "Only viewed recordings")
+            (@arg CHANNEL: --channel +takes_value "Only recordings from this channel, e.g. \"BBC 2 England\"")
+            (@arg GENRE: --genre +takes_value "Only recordings of this genre, e.g. Documentary")
+            (@arg SERIES: --series +takes_value "Only recordings with this series ID")
+            (@arg TITLE: --title +takes_value "Only recordings with titles matching this regex")
+            (@arg SINCE: --since +takes_value "Only recordings made on or after this date or RFC 3339 time")
+            (@arg BEFORE: --before +takes_value "Only recordings made before this date or RFC 3339 time")
+            (@arg MIN_DURATION: --("min-duration") +takes_value "Only recordings at least this long, e.g. 30m")
+            (@arg MAX_DURATION: --("max-duration") +takes_value "Only recordings at most this long, e.g. 1h30m")
+            (@arg TIME_ORDER: -t conflicts_with[SORT] "list in time order, oldest first")
+            (@arg REVERSE: -r "reverse the order")
+            (@arg SORT: -s --sort +takes_value "sort by fields, descending if prefixed by `-`, e.g. title,channel,-duration")
+            (@arg FORMAT: -o --output +takes_value "Output: JSON|NDJSON|CSV. NDJSON and CSV are written as recordings arrive")
+            (@arg STRICT: --strict "Fail if any recording cannot be fully parsed")
+            (@arg ALL_BOXES: --("all-boxes") "List the recordings of every skybox found, in one listing")
+            (@arg PAGE_SIZE: --("page-size") +takes_value "Recordings to fetch per request, 25 by default")
+            (@arg CONCURRENCY: --concurrency +takes_value "Most requests in flight at once, 4 by default")
+            (@arg LEAN: --lean "Ask the box for only the properties skybox parses, for smaller responses")
         )
         (@subcommand rm =>
             (about: "remove recordings")
             (@arg filenames: ... "recordings to remove, e.g. BOOK:688476834 BOOK:688555858")
+            (@arg ALL_BOXES: --("all-boxes") "Remove the recordings from whichever skybox holds them")
         )
         (@subcommand play =>
             (about: "play a recording")
             (@arg filename: +required "recording to play back, e.g. file://pvr/290B3177")
         )
+        (@subcommand info =>
+            (about: "show every property of a recording")
+            (@arg ITEM_ID: +required "recording to describe, e.g. BOOK:688476834")
+            (@arg JSON: --json "Print as JSON")
+        )
+        (@subcommand browse =>
+            (about: "browse the containers and items of the box, from the root by default")
+            (@arg OBJECT_ID: "container to list, e.g. 3 for the recordings")
+            (@arg METADATA: -m --metadata "describe the container or item itself, rather than what it contains")
+            (@arg JSON: --json "Print as JSON")
+        )
     );
 
     let matches = config.clone().get_matches();
     let scanner = Scanner::new();
+    let box_name = matches.value_of("BOX").map(String::from)
+        .or_else(|| std::env::var("SKYBOX").ok().filter(|name| !name.is_empty()));
 
     match matches.subcommand() {
-        Some(("scan",_)) => scanner.scan().await?,
+        Some(("scan", matches)) => scan(&build_scanner(matches)?, matches).await?,
+        Some(("add", matches)) => add(&scanner, matches).await?,
+        Some(("boxes", matches)) => manage_boxes(matches)?,
+        Some(("watch-network", matches)) => watch_network(&scanner, matches).await?,
+        Some(("ls", matches)) if matches.is_present("ALL_BOXES") => list_all_boxes(matches).await?,
+        Some(("rm", matches)) if matches.is_present("ALL_BOXES") => remove_all_boxes(matches).await?,
 
-        Some((subcommand, matches)) => {
-            if let Some(skybox) = scanner.get_selected() {
+        Some((subcommand @ ("ls" | "rm" | "play" | "info" | "browse"), matches)) => {
+            let mut registry = Registry::load()?;
+            let entry = registry.resolve(box_name.as_deref())?.clone();
+            let operation = |skybox| async move {
                 match subcommand {
-                    "ls" => skybox.list_items(matches).await?,
-                    "rm" => skybox.remove_items(matches).await?,
-                    "play" => skybox.play(matches).await?,
-                    _ => config.print_help()?
+                    "ls" => list_items(&configure_paging(skybox, matches)?, matches).await,
+                    "rm" => remove_items(&skybox, matches).await,
+                    "info" => info(&skybox, matches).await,
+                    "browse" => browse(&skybox, matches).await,
+                    _ => play(&skybox, matches).await
                 }
-            } else {
-                println!("Use subcommand `scan` to find a skybox");
-            }
+            };
+
+            scanner.with_rediscovery(&entry, operation, |skybox| {
+                registry.update(&entry.name, skybox)?;
+                registry.save()?;
+                eprintln!("Found {} again", skybox);
+                Ok(())
+            }).await?
         }
 
         _ => config.print_help()?
@@ -63,3 +165,639 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/**
+ * Build a scanner from the discovery options of the `scan` subcommand
+ */
+fn build_scanner(matches: &clap::ArgMatches) -> Result<Scanner> {
+    let mut scanner = Scanner::new();
+
+    if let Some(timeout) = matches.value_of("TIMEOUT") {
+        scanner = scanner.with_timeout(Duration::from_secs(timeout.parse()?));
+    }
+    if let Some(mx) = matches.value_of("MX") {
+        scanner = scanner.with_mx(mx.parse()?);
+    }
+    if let Some(repeats) = matches.value_of("REPEATS") {
+        scanner = scanner.with_repeats(repeats.parse()?);
+    }
+    if let Some(interface) = matches.value_of("INTERFACE") {
+        scanner = scanner.with_interface(interface.parse()
+            .map_err(|error| format!("Bad interface address: {}", error))?);
+    }
+    if let Some(host) = matches.value_of("UNICAST") {
+        let target = host.parse::<SocketAddr>()
+            .or_else(|_| host.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 1900)))
+            .map_err(|error| format!("Bad unicast address: {}", error))?;
+        scanner = scanner.with_target(target);
+    }
+
+    Ok(scanner)
+}
+
+async fn scan(scanner: &Scanner, matches: &clap::ArgMatches) -> Result<()> {
+    let spinner = ProgressBar::new_spinner();
+    spinner.enable_steady_tick(120);
+    spinner.set_message("Scanning...");
+
+    let report = scanner.scan_report().await?;
+    spinner.finish_with_message(format!("Found {} skybox", report.boxes.len()).as_str());
+
+    if matches.is_present("VERBOSE") {
+        eprintln!("{} responses, {} skyboxes, {} devices not used", report.responses, report.boxes.len(), report.diagnostics.len());
+        for diagnostic in &report.diagnostics {
+            eprintln!("warning: {}", diagnostic);
+        }
+    } else if !report.diagnostics.is_empty() {
+        eprintln!("{} devices could not be used, see --verbose", report.diagnostics.len());
+    }
+    let boxes = report.boxes;
+
+    let selection = if matches.is_present("FIRST") {
+        Some(Selection::First)
+    } else if let Some(index) = matches.value_of("INDEX") {
+        Some(Selection::Index(index.parse()?))
+    } else {
+        matches.value_of("HOST").map(|host| Selection::Host(host.into()))
+    };
+
+    if matches.is_present("JSON") {
+        let summaries: Vec<_> = boxes.iter().enumerate()
+            .map(|(index, skybox)| serde_json::json!({
+                "Index": index,
+                "Host": skybox.browse_url.host_str(),
+                "PlayUrl": skybox.play_url.as_str(),
+                "BrowseUrl": skybox.browse_url.as_str(),
+                "Device": skybox.device
+            }))
+            .collect();
+        println!("{}", serde_json::to_string(&summaries).expect("Cannot serialise result"));
+    } else {
+        for (i, skybox) in boxes.iter().enumerate() {
+            println!("{}:\t{}", i, skybox);
+        }
+    }
+
+    let skybox = match selection {
+        Some(selection) => selection.select(boxes)?,
+        None if boxes.is_empty() => return Err("No sky box found".into()),
+        None if matches.is_present("JSON") => return Ok(()),
+        None => choose(boxes)?
+    };
+
+    // Store the user's preferences
+    let name = skybox.save_box_as(matches.value_of("NAME"), matches.is_present("DEFAULT"))?;
+    eprintln!("Saved {} as `{}`", skybox, name);
+
+    Ok(())
+}
+
+async fn add(scanner: &Scanner, matches: &clap::ArgMatches) -> Result<()> {
+    let address = matches.value_of("ADDRESS").expect("Expecting argument");
+
+    let skybox = scanner.add(address).await?;
+    let name = skybox.save_box_as(matches.value_of("NAME"), matches.is_present("DEFAULT"))?;
+    eprintln!("Saved {} as `{}`", skybox, name);
+
+    Ok(())
+}
+
+async fn watch_network(scanner: &Scanner, matches: &clap::ArgMatches) -> Result<()> {
+    let interface = matches.value_of("INTERFACE").map(str::parse).transpose()
+        .map_err(|error| format!("Bad interface address: {}", error))?
+        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+    let mut watcher = Watcher::bind(interface)?;
+    eprintln!("Watching for skyboxes, press Ctrl-C to stop");
+
+    loop {
+        let change = watcher.next_change().await?;
+        match &change {
+            Change::Appeared(host) => println!("{} appeared: {}", Utc::now().to_rfc3339(), host),
+            Change::Left(host) => println!("{} left: {}", Utc::now().to_rfc3339(), host)
+        }
+
+        for sighting in watcher.table().sightings() {
+            println!("\t{}\t{}\tlast seen {}",
+                sighting.host,
+                sighting.services.values().next().map(String::as_str).unwrap_or_default(),
+                sighting.last_seen.to_rfc3339());
+        }
+
+        if let (Change::Appeared(host), true) = (&change, matches.is_present("UPDATE")) {
+            if let Some(sighting) = watcher.table().get(host) {
+                if let Err(error) = update_moved_box(scanner, sighting).await {
+                    eprintln!("warning: cannot update {}: {}", host, error);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Save the new address of a known box seen at another
+ */
+async fn update_moved_box(scanner: &Scanner, sighting: &Sighting) -> Result<()> {
+    let mut registry = Registry::load()?;
+    let moved = registry.boxes.iter().find(|entry|
+        entry.host().as_deref() != Some(sighting.host.as_str()) &&
+        entry.device.as_ref()
+            .and_then(|device| device.udn.as_ref())
+            .iter().any(|udn| sighting.services.values().any(|other| other == *udn)));
+
+    let name = match moved {
+        Some(entry) => entry.name.clone(),
+        None => return Ok(())
+    };
+
+    let locations: Vec<_> = sighting.locations.iter().cloned().collect();
+    let skybox = scanner.add_from_locations(&locations).await?;
+    registry.update(&name, &skybox)?;
+    registry.save()?;
+    eprintln!("Updated `{}` to {}", name, skybox);
+
+    Ok(())
+}
+
+fn manage_boxes(matches: &clap::ArgMatches) -> Result<()> {
+    let mut registry = Registry::load()?;
+
+    match matches.subcommand() {
+        Some(("rename", matches)) => registry.rename(
+            matches.value_of("NAME").expect("Expecting argument"),
+            matches.value_of("NEW_NAME").expect("Expecting argument"))?,
+        Some(("remove", matches)) => registry.remove(matches.value_of("NAME").expect("Expecting argument"))?,
+        Some(("default", matches)) => registry.set_default(matches.value_of("NAME").expect("Expecting argument"))?,
+        _ => {
+            for entry in &registry.boxes {
+                let marker = if registry.default.as_ref() == Some(&entry.name) { "*" } else { " " };
+                println!("{} {}\t{}\t{}\t{}", marker,
+                    entry.name,
+                    entry.host().unwrap_or_default(),
+                    entry.friendly_name().unwrap_or("-"),
+                    entry.last_seen.map(|time| time.to_rfc3339()).unwrap_or_else(|| "-".into()));
+            }
+            return Ok(());
+        }
+    }
+
+    registry.save()
+}
+
+/**
+ * Ask the user to choose a skybox
+ */
+fn choose(boxes: Vec<SkyBox>) -> Result<SkyBox> {
+    eprint!("Choose a skybox: ");
+
+    let line = io::stdin().lock().lines().next()
+        .ok_or("No skybox chosen")??;
+
+    Selection::Index(line.trim().parse()?).select(boxes)
+}
+
+fn configure_paging(mut skybox: SkyBox, matches: &clap::ArgMatches) -> Result<SkyBox> {
+    if let Some(page_size) = matches.value_of("PAGE_SIZE") {
+        skybox = skybox.with_page_size(page_size.parse()?);
+    }
+    if let Some(concurrency) = matches.value_of("CONCURRENCY") {
+        skybox = skybox.with_concurrency(concurrency.parse()?);
+    }
+    if matches.is_present("LEAN") {
+        skybox = skybox.with_properties(Item::PROPERTIES);
+    }
+    Ok(skybox.with_sort(build_sort(matches)?))
+}
+
+async fn list_items(skybox: &SkyBox, matches: &clap::ArgMatches) -> Result<()> {
+    if streamable(matches)? {
+        return stream_items(skybox, matches).await;
+    }
+
+    let start = std::time::Instant::now();
+    let progress = ProgressBar::new(0);
+    progress.println("Fetching recordings from skybox");
+
+    let listing = skybox.list_items_with_progress(|fetched, total| {
+        progress.set_length(total as u64);
+        progress.set_position(fetched as u64);
+    }).await?;
+
+    let msg = format!("Fetched {} items in {}s", listing.fetched(), start.elapsed().as_secs());
+    progress.finish_with_message(&msg);
+
+    show_listing(listing, matches)
+}
+
+/**
+ * Whether recordings can be written as they arrive: in a format written row by row, and in an order the box can page.
+ * Reversing the box's own order needs every recording first, and so does `--strict`, which must fail before any row is written.
+ */
+fn streamable(matches: &clap::ArgMatches) -> Result<bool> {
+    Ok(matches!(matches.value_of("FORMAT"), Some("CSV") | Some("NDJSON")) && !reversed_unsorted(matches)? && !matches.is_present("STRICT"))
+}
+
+async fn stream_items(skybox: &SkyBox, matches: &clap::ArgMatches) -> Result<()> {
+    let filter = build_filter(matches)?;
+    let progress = ProgressBar::new(0);
+    let mut lister = lister::build_lister(0, matches);
+
+    let mut fetched = 0;
+    let mut pages = Box::pin(skybox.pages());
+    while let Some((page, total)) = pages.try_next().await? {
+        fetched += page.fetched();
+        progress.set_length(total as u64);
+        progress.set_position(fetched as u64);
+
+        report_diagnostics(&page.diagnostics, false)?;
+        if closed_pipe(lister.list(&filter.apply(page.items)))? {
+            break;
+        }
+    }
+
+    progress.finish_and_clear();
+    closed_pipe(lister.close())?;
+    Ok(())
+}
+
+/**
+ * Whether the reader of stdout has gone, e.g. `head` has read enough, passing up any other write error
+ */
+fn closed_pipe(result: std::io::Result<()>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(false),
+        Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => Ok(true),
+        Err(error) => Err(error.into())
+    }
+}
+
+async fn list_all_boxes(matches: &clap::ArgMatches) -> Result<()> {
+    let fleet = Fleet::from_registry(&Registry::load()?)?
+        .configure(|skybox| configure_paging(skybox, matches))?;
+    if fleet.is_empty() {
+        return Err("No skybox found, use subcommand `scan` or `add` to find one".into());
+    }
+
+    // Interleaved pages are in no order across boxes
+    if streamable(matches)? && build_sort(matches)?.is_empty() {
+        return stream_all_boxes(&fleet, matches).await;
+    }
+
+    let start = std::time::Instant::now();
+    let progress = ProgressBar::new(0);
+    progress.println("Fetching recordings from every skybox");
+
+    // Fetched and total per box
+    let counts = RefCell::new(HashMap::new());
+    let result = fleet.list_items_with_progress(|name, fetched, total| {
+        let mut counts = counts.borrow_mut();
+        counts.insert(name.to_owned(), (fetched, total));
+        progress.set_length(counts.values().map(|(_, total)| *total as u64).sum());
+        progress.set_position(counts.values().map(|(fetched, _)| *fetched as u64).sum());
+    }).await;
+
+    let msg = format!("Fetched {} items in {}s", result.fetched(), start.elapsed().as_secs());
+    progress.finish_with_message(&msg);
+
+    for (name, error) in &result.failures {
+        eprintln!("warning: cannot list skybox `{}`: {}", name, error);
+    }
+    if result.failures.len() == fleet.len() {
+        return Err("Cannot list any skybox".into());
+    }
+
+    show_fleet_listing(result, matches)
+}
+
+async fn stream_all_boxes(fleet: &Fleet, matches: &clap::ArgMatches) -> Result<()> {
+    let filter = build_filter(matches)?;
+    let progress = ProgressBar::new(0);
+    let mut lister = lister::build_lister(0, matches);
+
+    // Fetched and total per box
+    let mut counts = HashMap::new();
+    let mut failures = 0;
+    let mut pages = fleet.pages();
+    while let Some((name, page)) = pages.next().await {
+        let (page, total) = match page {
+            Ok(page) => page,
+            Err(error) => {
+                progress.println(format!("warning: cannot list skybox `{}`: {}", name, error));
+                failures += 1;
+                continue;
+            }
+        };
+
+        let count = counts.entry(name).or_insert((0, 0));
+        *count = (count.0 + page.fetched(), total);
+        progress.set_length(counts.values().map(|(_, total)| *total as u64).sum());
+        progress.set_position(counts.values().map(|(fetched, _)| *fetched as u64).sum());
+
+        let diagnostics: Vec<_> = page.diagnostics.iter()
+            .map(|diagnostic| format!("[{}] {}", name, diagnostic))
+            .collect();
+        report_diagnostics(&diagnostics, false)?;
+
+        let items: Vec<_> = filter.apply(page.items).into_iter()
+            .map(|item| FleetItem { skybox: name.to_owned(), item })
+            .collect();
+        if closed_pipe(lister.list_fleet(&items))? {
+            break;
+        }
+    }
+
+    progress.finish_and_clear();
+    if failures == fleet.len() {
+        return Err("Cannot list any skybox".into());
+    }
+    closed_pipe(lister.close())?;
+    Ok(())
+}
+
+fn show_listing(listing: Listing, matches: &clap::ArgMatches) -> Result<()> {
+    report_diagnostics(&listing.diagnostics, matches.is_present("STRICT"))?;
+
+    // Already sorted by the box, or by `SkyBox` when the box cannot sort
+    let mut items = build_filter(matches)?.apply(listing.items);
+    if reversed_unsorted(matches)? {
+        items.reverse();
+    }
+
+    let mut lister = lister::build_lister(items.len(), matches);
+    closed_pipe(lister.list(&items).and_then(|_| lister.close()))?;
+
+    Ok(())
+}
+
+fn show_fleet_listing(listing: FleetListing, matches: &clap::ArgMatches) -> Result<()> {
+    let diagnostics: Vec<_> = listing.diagnostics.iter()
+        .map(|(name, diagnostic)| format!("[{}] {}", name, diagnostic))
+        .collect();
+    report_diagnostics(&diagnostics, matches.is_present("STRICT"))?;
+
+    let filter = build_filter(matches)?;
+    let mut items: Vec<_> = listing.items.into_iter().filter(|fleet_item| filter.matches(&fleet_item.item)).collect();
+    // Each box sorted only its own recordings, so merging them needs one sort here
+    let sort = build_sort(matches)?;
+    if !sort.is_empty() {
+        items.sort_by(|a, b| sort.compare(&a.item, &b.item));
+    } else if matches.is_present("REVERSE") {
+        items.reverse();
+    }
+
+    let mut lister = lister::build_lister(items.len(), matches);
+    closed_pipe(lister.list_fleet(&items).and_then(|_| lister.close()))?;
+
+    Ok(())
+}
+
+/**
+ * Warn of each parsing problem on stderr, or fail if `strict`
+ */
+fn report_diagnostics<D: std::fmt::Display>(diagnostics: &[D], strict: bool) -> Result<()> {
+    let prefix = if strict { "error" } else { "warning" };
+    for diagnostic in diagnostics {
+        eprintln!("{}: {}", prefix, diagnostic);
+    }
+
+    if strict && !diagnostics.is_empty() {
+        return Err(format!("{} problems parsing recordings", diagnostics.len()).into());
+    }
+    Ok(())
+}
+
+/**
+ * Build a filter from the filtering arguments of any subcommand
+ */
+fn build_filter(matches: &clap::ArgMatches) -> Result<Filter> {
+    let mut filter = Filter::new();
+
+    if matches.is_present("UNWATCHED") {
+        filter.viewed = Some(false);
+    } else if matches.is_present("VIEWED") {
+        filter.viewed = Some(true);
+    }
+
+    filter.channel_name = matches.value_of("CHANNEL").map(String::from);
+    filter.series_id = matches.value_of("SERIES").map(String::from);
+    filter.service_type = matches.value_of("GENRE").map(str::parse).transpose()?;
+    filter.title = matches.value_of("TITLE").map(regex::Regex::new).transpose()
+        .map_err(|error| format!("Bad title regex: {}", error))?;
+
+    filter.recorded_since = matches.value_of("SINCE").map(parse_date_time).transpose()?;
+    filter.recorded_before = matches.value_of("BEFORE").map(parse_date_time).transpose()?;
+    filter.min_duration = matches.value_of("MIN_DURATION").map(parse_duration).transpose()?;
+    filter.max_duration = matches.value_of("MAX_DURATION").map(parse_duration).transpose()?;
+
+    Ok(filter)
+}
+
+/**
+ * The order given by `--sort` or `-t`, in the opposite direction given `-r`
+ */
+fn build_sort(matches: &clap::ArgMatches) -> Result<Sort> {
+    let sort = if matches.is_present("TIME_ORDER") {
+        "time".parse()?
+    } else {
+        matches.value_of("SORT").map(str::parse).transpose()?.unwrap_or_else(Sort::default)
+    };
+
+    Ok(if matches.is_present("REVERSE") { sort.reversed() } else { sort })
+}
+
+/**
+ * Whether `-r` is given without any sort, reversing the box's own order rather than a sort
+ */
+fn reversed_unsorted(matches: &clap::ArgMatches) -> Result<bool> {
+    Ok(matches.is_present("REVERSE") && build_sort(matches)?.is_empty())
+}
+
+async fn remove_items(skybox: &SkyBox, matches: &clap::ArgMatches) -> Result<()> {
+    let item_ids = matches.values_of("filenames")
+        .ok_or("Require at least one item to remove")?;
+
+    for item_id in item_ids {
+        eprintln!("Removing: {} using {}", item_id, skybox.browse_url);
+        skybox.remove_item(item_id).await?;
+        println!("removed: {}", item_id);
+    }
+
+    Ok(())
+}
+
+async fn remove_all_boxes(matches: &clap::ArgMatches) -> Result<()> {
+    let fleet = Fleet::from_registry(&Registry::load()?)?;
+    let item_ids = matches.values_of("filenames")
+        .ok_or("Require at least one item to remove")?;
+
+    for item_id in item_ids {
+        let mut removed = false;
+        let mut unasked = 0;
+        for (name, outcome) in fleet.remove_item(item_id).await {
+            match outcome {
+                Ok(()) => {
+                    println!("removed: {} from {}", item_id, name);
+                    removed = true;
+                }
+                Err(Error(ErrorKind::NoSuchObject(..), _)) => (),
+                Err(error) => {
+                    eprintln!("warning: cannot remove {} from skybox `{}`: {}", item_id, name, error);
+                    unasked += 1;
+                }
+            }
+        }
+
+        // Only every box answering that it does not hold the recording means it is nowhere
+        if !removed && unasked > 0 {
+            return Err(format!("Cannot remove {}: {} skybox could not be asked", item_id, unasked).into());
+        }
+        if !removed {
+            return Err(ErrorKind::NoSuchObject(701, format!("No skybox holds {}", item_id)).into());
+        }
+    }
+
+    Ok(())
+}
+
+async fn play(skybox: &SkyBox, matches: &clap::ArgMatches) -> Result<()> {
+    let item_res = matches.value_of("filename").expect("Expecting argument");
+
+    skybox.play(item_res).await?;
+    println!("Playing: {}", item_res);
+
+    Ok(())
+}
+
+async fn browse(skybox: &SkyBox, matches: &clap::ArgMatches) -> Result<()> {
+    let object_id = matches.value_of("OBJECT_ID").unwrap_or(ROOT_CONTAINER);
+
+    let objects = if matches.is_present("METADATA") {
+        vec![skybox.browse_metadata(object_id).await?]
+    } else {
+        skybox.browse_children(object_id).await?
+    };
+
+    if matches.is_present("JSON") {
+        println!("{}", serde_json::to_string(&objects).expect("Cannot serialise result"));
+        return Ok(());
+    }
+
+    for object in objects.iter() {
+        let kind = match object.child_count {
+            Some(child_count) if object.container => format!("container of {}", child_count),
+            _ if object.container => "container".to_owned(),
+            _ => object.class.clone().unwrap_or_else(|| "item".to_owned())
+        };
+        println!("{}\t{}\t{}", object.id, kind, object.title.as_deref().unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+async fn info(skybox: &SkyBox, matches: &clap::ArgMatches) -> Result<()> {
+    let item_id = matches.value_of("ITEM_ID").expect("Expecting argument");
+
+    let (item, diagnostics) = skybox.item(item_id).await?;
+    report_diagnostics(&diagnostics, false)?;
+
+    if matches.is_present("JSON") {
+        println!("{}", serde_json::to_string_pretty(&item).expect("Cannot serialise result"));
+        return Ok(());
+    }
+
+    let rows = properties(&item);
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+    for (name, value) in rows {
+        println!("{:width$}  {}", name, value, width = width);
+    }
+
+    Ok(())
+}
+
+/**
+ * Every property of a recording as `(name, value)` rows, in the order of the fields of `Item`,
+ * each nested field named by its path, e.g. `Resource.Size`. Absent values are shown as `-`.
+ */
+fn properties(item: &Item) -> Vec<(&'static str, String)> {
+    fn shown<T: ToString>(value: Option<T>) -> String {
+        value.map_or_else(|| "-".into(), |value| value.to_string())
+    }
+    let resource = item.resource.as_ref();
+    let flags = item.flags.as_ref();
+    let booking = item.booking.as_ref();
+    let status = item.recording_status.as_ref();
+
+    vec![
+        ("Id", item.id.clone()),
+        ("Res", item.res.clone()),
+        ("Title", item.title.clone()),
+        ("Description", shown(item.description.as_ref())),
+        ("Viewed", item.viewed.to_string()),
+        ("RecordedStarttime", item.recorded_starttime.to_rfc3339()),
+        ("RecordedDuration", item.recorded_duration.to_string()),
+        ("ChannelName", item.channel_name.clone()),
+        ("ChannelNumber", shown(item.channel_number)),
+        ("ChannelId", shown(item.channel_id.as_ref())),
+        ("ProgramId", shown(item.program_id.as_ref())),
+        ("SeriesId", shown(item.series_id.as_ref())),
+        ("ServiceType", format!("{:?}", item.service_type)),
+        ("ScheduledStarttime", shown(item.scheduled_starttime.map(|time| time.to_rfc3339()))),
+        ("ScheduledEndtime", shown(item.scheduled_endtime.map(|time| time.to_rfc3339()))),
+        ("Resource.Size", shown(resource.map(|resource| resource.size))),
+        ("Resource.Duration", shown(resource.map(|resource| resource.duration))),
+        ("Resource.ProtocolInfo", shown(resource.map(|resource| &resource.protocol_info))),
+        ("Flags.Hd", shown(flags.map(|flags| flags.hd))),
+        ("Flags.Uhd", shown(flags.map(|flags| flags.uhd))),
+        ("Flags.Widescreen", shown(flags.map(|flags| flags.widescreen))),
+        ("Flags.HasAudioDesc", shown(flags.map(|flags| flags.has_audio_desc))),
+        ("Flags.HasForeignSubtitles", shown(flags.map(|flags| flags.has_foreign_subtitles))),
+        ("Flags.IsAdult", shown(flags.map(|flags| flags.is_adult))),
+        ("Flags.Is3d", shown(flags.map(|flags| flags.is_3d))),
+        ("Booking.Keep", shown(booking.map(|booking| booking.keep))),
+        ("Booking.Lock", shown(booking.map(|booking| booking.lock))),
+        ("RecordingStatus.Status", shown(status.map(|status| status.status))),
+        ("RecordingStatus.Failed", shown(status.map(|status| status.failed))),
+        ("RecordingStatus.ContentStatus", shown(status.map(|status| status.content_status))),
+        ("RecordingStatus.Exception", shown(status.map(|status| status.exception))),
+        ("RecordingStatus.RecState", shown(status.map(|status| status.rec_state))),
+        ("LastPlaybackPosition", shown(item.last_playback_position)),
+        ("LastViewedTime", shown(item.last_viewed_time.map(|time| time.to_rfc3339()))),
+        ("EstimatedBitRate", shown(item.estimated_bit_rate)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * The first recording of the simulator's fixture, which the binary cannot reach as it is test-only in the library
+     */
+    pub(crate) fn first_item() -> Item {
+        let document = roxmltree::Document::parse(include_str!("fixtures/recordings.xml")).unwrap();
+        Item::build(document.root_element().first_element_child().unwrap()).unwrap()
+    }
+
+    // Every field of a JSON object, each nested field named by its path
+    fn flatten(prefix: &str, value: &serde_json::Value, keys: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(fields) => for (name, field) in fields {
+                let path = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+                flatten(&path, field, keys);
+            },
+            _ => keys.push(prefix.to_owned())
+        }
+    }
+
+    #[test]
+    fn test_properties() {
+        let item = first_item();
+        let mut keys = Vec::new();
+        flatten("", &serde_json::to_value(&item).unwrap(), &mut keys);
+
+        let names: Vec<_> = properties(&item).into_iter().map(|(name, _)| name).collect();
+        assert!(keys.len() > 30);
+        for key in keys {
+            assert!(names.contains(&key.as_str()), "`info` does not show {}", key);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use super::common::errors::{Result, ResultExt};
+use super::device::DeviceInfo;
+use super::skybox::{APP_INFO, SkyBox};
+
+use chrono::{DateTime, Utc};
+use preferences::{Preferences, PreferencesError};
+use std::io;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+const PREFS_KEY: &str = "skybox/boxes";
+
+/**
+ * A SkyBox known by name
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BoxEntry {
+    pub name: String,
+    pub play_url: String,
+    pub browse_url: String,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub device: Option<DeviceInfo>,
+}
+
+impl BoxEntry {
+
+    pub(crate) fn new(name: String, skybox: &SkyBox) -> BoxEntry {
+        BoxEntry {
+            name,
+            play_url: skybox.play_url.to_string(),
+            browse_url: skybox.browse_url.to_string(),
+            last_seen: Some(Utc::now()),
+            device: skybox.device.clone(),
+        }
+    }
+
+    pub fn skybox(&self) -> Result<SkyBox> {
+        let skybox = SkyBox::new(Url::parse(&self.play_url)?, Url::parse(&self.browse_url)?);
+        Ok(match &self.device {
+            Some(device) => skybox.with_device(device.clone()),
+            None => skybox
+        })
+    }
+
+    pub fn friendly_name(&self) -> Option<&str> {
+        self.device.as_ref()?.friendly_name.as_deref()
+    }
+
+    pub fn host(&self) -> Option<String> {
+        Url::parse(&self.browse_url).ok()?.host_str().map(String::from)
+    }
+}
+
+/**
+ * Every SkyBox found so far, and which to use when none is named
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Registry {
+    pub default: Option<String>,
+    pub boxes: Vec<BoxEntry>,
+}
+
+impl Registry {
+
+    /**
+     * Load the registry, adopting the single box saved by earlier versions if none has been saved yet
+     */
+    pub fn load() -> Result<Registry> {
+        Registry::loaded(<Registry as Preferences>::load(&APP_INFO, PREFS_KEY))
+    }
+
+    /**
+     * The registry loaded, or a new one if there was none to load.
+     * Any other failure is passed up, rather than have the next `save` overwrite the boxes saved.
+     */
+    fn loaded(result: std::result::Result<Registry, PreferencesError>) -> Result<Registry> {
+        match result {
+            Ok(registry) => Ok(registry),
+            Err(PreferencesError::Io(ref error)) if error.kind() == io::ErrorKind::NotFound => {
+                let mut registry = Registry::default();
+                if let Ok(skybox) = SkyBox::load_legacy_box() {
+                    registry.add(&skybox, None)?;
+                }
+                Ok(registry)
+            }
+            Err(error) => Err(error).chain_err(|| "Cannot load saved skyboxes")
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        Preferences::save(self, &APP_INFO, PREFS_KEY)
+            .map_err(|error| format!("Cannot save skyboxes: {}", error).into())
+    }
+
+    /**
+     * Add or refresh a box, returning its name.
+     * A known box keeps its name unless `name` is given, even if its IP address has changed,
+     * but `name` cannot be taken from another box.
+     * The first box added becomes the default.
+     */
+    pub fn add(&mut self, skybox: &SkyBox, name: Option<&str>) -> Result<String> {
+        let host = skybox.browse_url.host_str().map(String::from);
+        let known = |entry: &BoxEntry| match (&entry.device, &skybox.device) {
+            (Some(device), Some(other)) if device.same_device(other) => true,
+            _ => host.is_some() && entry.host() == host
+        };
+
+        if let Some(name) = name {
+            if self.entry(name).iter().any(|entry| !known(entry)) {
+                return Err(format!("A skybox called `{}` already exists", name).into());
+            }
+        }
+
+        let name = name.map(String::from)
+            .or_else(|| self.boxes.iter().find(|entry| known(entry)).map(|entry| entry.name.clone()))
+            .unwrap_or_else(|| self.unused_name(host.as_deref().unwrap_or("skybox")));
+
+        // Replace any entry of the same name, or for the same box
+        let replaced = |entry: &BoxEntry| entry.name == name || known(entry);
+        if self.boxes.iter().any(|entry| replaced(entry) && self.default.as_ref() == Some(&entry.name)) {
+            self.default = Some(name.clone());
+        }
+        self.boxes.retain(|entry| !replaced(entry));
+        self.boxes.push(BoxEntry::new(name.clone(), skybox));
+
+        if self.default.is_none() {
+            self.default = Some(name.clone());
+        }
+        Ok(name)
+    }
+
+    /**
+     * Point the box called `name` at the address it has been found at since, keeping its name
+     */
+    pub fn update(&mut self, name: &str, skybox: &SkyBox) -> Result<()> {
+        let entry = self.boxes.iter_mut().find(|entry| entry.name == name)
+            .ok_or_else(|| format!("No skybox called `{}`", name))?;
+
+        let device = skybox.device.clone().or_else(|| entry.device.take());
+        *entry = BoxEntry { device, ..BoxEntry::new(name.into(), skybox) };
+        Ok(())
+    }
+
+    fn unused_name(&self, base: &str) -> String {
+        let mut name = base.to_owned();
+        let mut n = 2;
+        while self.entry(&name).is_some() {
+            name = format!("{}-{}", base, n);
+            n += 1;
+        }
+        name
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&BoxEntry> {
+        self.boxes.iter().find(|entry| entry.name == name)
+    }
+
+    /**
+     * The box called `name`, or the default box if no name is given
+     */
+    pub fn get(&self, name: Option<&str>) -> Result<SkyBox> {
+        self.resolve(name)?.skybox()
+    }
+
+    /**
+     * The entry for the box called `name`, or for the default box if no name is given
+     */
+    pub fn resolve(&self, name: Option<&str>) -> Result<&BoxEntry> {
+        let name = match name.or(self.default.as_deref()) {
+            Some(name) => name,
+            None => return Err("No skybox chosen, use subcommand `scan` or `add` to find one".into())
+        };
+
+        Ok(self.entry(name).ok_or_else(|| format!("No skybox called `{}`", name))?)
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if self.entry(new_name).is_some() {
+            return Err(format!("A skybox called `{}` already exists", new_name).into());
+        }
+
+        let entry = self.boxes.iter_mut().find(|entry| entry.name == name)
+            .ok_or_else(|| format!("No skybox called `{}`", name))?;
+        entry.name = new_name.into();
+
+        if self.default.as_deref() == Some(name) {
+            self.default = Some(new_name.into());
+        }
+        Ok(())
+    }
+
+    /**
+     * Forget a box. If it was the default, the first remaining box becomes the default.
+     */
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if self.entry(name).is_none() {
+            return Err(format!("No skybox called `{}`", name).into());
+        }
+
+        self.boxes.retain(|entry| entry.name != name);
+        if self.default.as_deref() == Some(name) {
+            self.default = self.boxes.first().map(|entry| entry.name.clone());
+        }
+        Ok(())
+    }
+
+    pub fn set_default(&mut self, name: &str) -> Result<()> {
+        if self.entry(name).is_none() {
+            return Err(format!("No skybox called `{}`", name).into());
+        }
+
+        self.default = Some(name.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::skybox_at;
+
+    fn names(registry: &Registry) -> Vec<&str> {
+        registry.boxes.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_add() {
+        let mut registry = Registry::default();
+
+        assert_eq!("192.168.2.15", registry.add(&skybox_at("192.168.2.15"), None).unwrap());
+        assert_eq!("bedroom", registry.add(&skybox_at("192.168.2.152"), Some("bedroom")).unwrap());
+        assert_eq!(Some("192.168.2.15"), registry.default.as_deref());
+
+        // A box at a known host keeps its name
+        assert_eq!("bedroom", registry.add(&skybox_at("192.168.2.152"), None).unwrap());
+        assert_eq!(vec!["192.168.2.15", "bedroom"], names(&registry));
+
+        // Renaming when re-added
+        assert_eq!("lounge", registry.add(&skybox_at("192.168.2.15"), Some("lounge")).unwrap());
+        assert_eq!(vec!["bedroom", "lounge"], names(&registry));
+        assert_eq!(Some("lounge"), registry.default.as_deref());
+        assert!(registry.boxes.iter().all(|entry| entry.last_seen.is_some()));
+
+        // A name taken by another box
+        assert!(registry.add(&skybox_at("192.168.2.99"), Some("bedroom")).is_err());
+        assert_eq!(Some("192.168.2.152".into()), registry.entry("bedroom").unwrap().host());
+    }
+
+    #[test]
+    fn test_update() {
+        let mut registry = Registry::default();
+        registry.add(&skybox_at("192.168.2.15"), Some("lounge")).unwrap();
+        registry.add(&skybox_at("192.168.2.152"), Some("bedroom")).unwrap();
+
+        registry.update("lounge", &skybox_at("192.168.2.99")).unwrap();
+        assert_eq!(vec!["lounge", "bedroom"], names(&registry));
+        assert_eq!(Some("192.168.2.99".into()), registry.entry("lounge").unwrap().host());
+        assert_eq!(Some("lounge"), registry.default.as_deref());
+        assert!(registry.update("kitchen", &skybox_at("192.168.2.99")).is_err());
+    }
+
+    #[test]
+    fn test_add_moved_box() {
+        let device = DeviceInfo {
+            friendly_name: Some("Sky+HD".into()),
+            udn: Some("uuid:444D5376-3247-536B-7953-00000000BEEF".into()),
+            ..DeviceInfo::default()
+        };
+        let mut registry = Registry::default();
+        registry.add(&skybox_at("192.168.2.15").with_device(device.clone()), Some("lounge")).unwrap();
+        registry.add(&skybox_at("192.168.2.152"), Some("bedroom")).unwrap();
+
+        // A new DHCP lease
+        assert_eq!("lounge", registry.add(&skybox_at("192.168.2.99").with_device(device), None).unwrap());
+        assert_eq!(vec!["bedroom", "lounge"], names(&registry));
+
+        let lounge = registry.entry("lounge").unwrap();
+        assert_eq!(Some("192.168.2.99".into()), lounge.host());
+        assert_eq!(Some("Sky+HD"), lounge.friendly_name());
+        assert_eq!(Some("Sky+HD at 192.168.2.99".into()), lounge.skybox().ok().map(|skybox| skybox.to_string()));
+    }
+
+    #[test]
+    fn test_load_unparseable() {
+        let mut saved = Vec::new();
+        let mut registry = Registry::default();
+        registry.add(&skybox_at("192.168.2.15"), Some("lounge")).unwrap();
+        registry.save_to(&mut saved).unwrap();
+
+        let registry = Registry::loaded(Registry::load_from(&mut saved.as_slice())).unwrap();
+        assert_eq!(vec!["lounge"], names(&registry));
+
+        let error = Registry::loaded(Registry::load_from(&mut "{\"Default\": ".as_bytes())).unwrap_err();
+        assert_eq!("Cannot load saved skyboxes", error.to_string());
+
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "Permission denied");
+        assert!(Registry::loaded(Err(PreferencesError::Io(denied))).is_err());
+    }
+
+    #[test]
+    fn test_get() {
+        let mut registry = Registry::default();
+        assert!(registry.get(None).is_err());
+
+        registry.add(&skybox_at("192.168.2.15"), Some("lounge")).unwrap();
+        registry.add(&skybox_at("192.168.2.152"), Some("bedroom")).unwrap();
+
+        assert_eq!(Some("192.168.2.15"), registry.get(None).unwrap().browse_url.host_str());
+        assert_eq!(Some("192.168.2.152"), registry.get(Some("bedroom")).unwrap().browse_url.host_str());
+        assert!(registry.get(Some("kitchen")).is_err());
+    }
+
+    #[test]
+    fn test_rename_remove_default() {
+        let mut registry = Registry::default();
+        registry.add(&skybox_at("192.168.2.15"), Some("lounge")).unwrap();
+        registry.add(&skybox_at("192.168.2.152"), Some("bedroom")).unwrap();
+
+        registry.rename("lounge", "living-room").unwrap();
+        assert_eq!(Some("living-room"), registry.default.as_deref());
+        assert!(registry.rename("bedroom", "living-room").is_err());
+        assert!(registry.rename("kitchen", "larder").is_err());
+
+        registry.set_default("bedroom").unwrap();
+        assert_eq!(Some("bedroom"), registry.default.as_deref());
+        assert!(registry.set_default("kitchen").is_err());
+
+        registry.remove("bedroom").unwrap();
+        assert_eq!(vec!["living-room"], names(&registry));
+        assert_eq!(Some("living-room"), registry.default.as_deref());
+        assert!(registry.remove("bedroom").is_err());
+    }
+}
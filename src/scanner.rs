@@ -1,110 +1,330 @@
 use super::common::{SKY_BROWSE, SKY_PLAY};
 use super::common::errors::*;
+use super::device::DeviceInfo;
+use super::registry::BoxEntry;
 use super::skybox::SkyBox;
 
-use indicatif::ProgressBar;
-use ssdp_client::{URN, SearchTarget};
-use std::collections::HashMap;
-use std::io::{self, BufRead};
+use super::ssdp::{self, Message, SearchOptions};
+
+use ssdp_client::URN;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::time::Duration;
 use url::Url;
 
-use futures::prelude::*;
-use futures::join;
+use std::future::Future;
+use futures::future::join_all;
+
+const DESCRIPTION_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Where SkyBoxes are known to serve their UPnP device descriptions
+const DESCRIPTION_PORTS: [u16; 3] = [49153, 49152, 49154];
+const DESCRIPTION_PATHS: [&str; 3] = ["/description0.xml", "/description1.xml", "/description2.xml"];
 
-const TIMEOUT: Duration = Duration::from_secs(5);
+/**
+ * How to choose one of the discovered SkyBoxes without asking
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    First,
+    Index(usize),
+    Host(String),
+    Device(DeviceInfo),
+}
+
+impl Selection {
+
+    pub fn select(&self, boxes: Vec<SkyBox>) -> Result<SkyBox> {
+        let found = match self {
+            Selection::First => boxes.into_iter().next(),
+            Selection::Index(index) => boxes.into_iter().nth(*index),
+            Selection::Host(host) => boxes.into_iter().find(|skybox| skybox.browse_url.host_str() == Some(host.as_str())),
+            Selection::Device(device) => boxes.into_iter().find(|skybox| skybox.device.iter().any(|other| device.same_device(other)))
+        };
+
+        found.ok_or_else(|| match self {
+            Selection::First => "No sky box found".into(),
+            Selection::Index(index) => format!("No sky box at index {}", index).into(),
+            Selection::Host(host) => format!("No sky box found at {}", host).into(),
+            Selection::Device(device) => format!("Cannot find sky box {}", device).into()
+        })
+    }
+}
+
+/**
+ * A device that answered the search, but could not be used
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanDiagnostic {
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for ScanDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/**
+ * SkyBoxes found by a scan, and the problems with any other devices that answered
+ */
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    pub responses: usize,
+    pub boxes: Vec<SkyBox>,
+    pub diagnostics: Vec<ScanDiagnostic>,
+}
 
 /**
  * Scan for SkyBoxes
  */
+#[derive(Default)]
 pub struct Scanner {
-    // ...
+    options: SearchOptions,
+    http: reqwest::Client
+}
+
+/**
+ * The SkyBox services offered by a device, from one or more of its description documents
+ */
+#[derive(Debug, Default)]
+struct Description {
+    play_url: Option<Url>,
+    browse_url: Option<Url>,
+    device: Option<DeviceInfo>,
+}
+
+impl Description {
+
+    fn merge(&mut self, other: Description) {
+        // Identify the box by the device offering SkyBrowse
+        let prefer_other = self.browse_url.is_none() && other.browse_url.is_some();
+        self.device = if prefer_other {
+            other.device.or_else(|| self.device.take())
+        } else {
+            self.device.take().or(other.device)
+        };
+        self.play_url = self.play_url.take().or(other.play_url);
+        self.browse_url = self.browse_url.take().or(other.browse_url);
+    }
+
+    fn skybox(&self) -> Option<SkyBox> {
+        let skybox = SkyBox::new(self.play_url.clone()?, self.browse_url.clone()?);
+        Some(match &self.device {
+            Some(device) => skybox.with_device(device.clone()),
+            None => skybox
+        })
+    }
 }
 
 impl Scanner {
     pub fn new() -> Self {
-        Scanner{}
+        Scanner::default()
     }
 
-    pub fn get_selected(&self) -> Option<SkyBox> {
-        SkyBox::load_box().ok()
+    /**
+     * How long to wait for responses, 5s by default
+     */
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = timeout;
+        self
     }
 
-    pub async fn scan(&self) -> Result<()> {
+    /**
+     * The most seconds a device should wait before responding, 2 by default
+     */
+    pub fn with_mx(mut self, mx: u8) -> Self {
+        self.options.mx = mx;
+        self
+    }
 
-        let spinner = ProgressBar::new_spinner();
-        spinner.enable_steady_tick(120);
-        spinner.set_message("Scanning...");
+    /**
+     * How many times to send each search, as UDP may drop some, once by default
+     */
+    pub fn with_repeats(mut self, repeats: usize) -> Self {
+        self.options.repeats = repeats;
+        self
+    }
 
-        let play: &SearchTarget = &SKY_PLAY.into(); //NB: shame this cannot be done in the `search` calls
-        let browse: &SearchTarget = &SKY_BROWSE.into();
+    /**
+     * Search from the network interface with this address, rather than whichever the OS picks
+     */
+    pub fn with_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.options.interface = interface;
+        self
+    }
 
-        let (sky_play, sky_browse) = (&SKY_PLAY, &SKY_BROWSE);
-        let (play_urls, browse_urls) = join!(
-            self.ssdp_search(play, sky_play),
-            self.ssdp_search(browse, sky_browse)
-        );
+    /**
+     * Search by unicast to one host, rather than by multicast to every host
+     */
+    pub fn with_target(mut self, target: SocketAddr) -> Self {
+        self.options.target = target;
+        self
+    }
 
-        let play_urls = play_urls?;
-        let browse_urls = browse_urls?;
+    /**
+     * Search the LAN for SkyBoxes, ordered by host
+     */
+    pub async fn discover(&self) -> Result<Vec<SkyBox>> {
+        Ok(self.scan_report().await?.boxes)
+    }
 
-        // Merge/Zip two URL dicts together
-        let mut boxes: Vec<SkyBox> = Vec::new();
-        for (ip_addr, browse_url) in browse_urls {
-            let play_url = match play_urls.get(&ip_addr) {
-                Some(url) => url,
+    /**
+     * Search the LAN for SkyBoxes, ordered by host, reporting devices that answered but could not be used
+     */
+    pub async fn scan_report(&self) -> Result<ScanReport> {
+        let responses = ssdp::search(&[SKY_PLAY.to_string(), SKY_BROWSE.to_string()], &self.options).await?;
+        Ok(self.report(&responses).await)
+    }
+
+    /**
+     * Fetch the description of each device that responded, once and concurrently, then gather the services of each host
+     */
+    async fn report(&self, responses: &[Message]) -> ScanReport {
+        let locations = unique_locations(responses);
+        let descriptions = join_all(locations.iter().map(|location| async move {
+            self.describe(&Url::parse(location)?, DESCRIPTION_TIMEOUT).await
+        })).await;
+
+        let mut report = ScanReport { responses: responses.len(), ..ScanReport::default() };
+        let mut hosts: BTreeMap<String, Description> = BTreeMap::new();
+        for (location, description) in locations.iter().zip(descriptions) {
+            match description {
+                Ok((host, description)) => hosts.entry(host).or_default().merge(description),
+                Err(error) => report.diagnostics.push(ScanDiagnostic {
+                    location: location.to_string(),
+                    message: error.to_string()
+                })
+            }
+        }
+
+        for (host, description) in hosts {
+            match description.skybox() {
+                Some(skybox) => report.boxes.push(skybox),
                 None => {
-                    eprintln!("No matching URL {}", ip_addr);
-                    continue;
+                    let missing = if description.play_url.is_none() { SKY_PLAY } else { SKY_BROWSE };
+                    report.diagnostics.push(ScanDiagnostic {
+                        location: host.clone(),
+                        message: format!("No {} service at {}", missing, host)
+                    });
                 }
-            };
-
-            println!("Found {} and {}", play_url, browse_url);
-            let skybox = SkyBox::new(play_url.clone(), browse_url);
-            boxes.push(skybox);
+            }
         }
 
+        report
+    }
 
-        if boxes.is_empty() {
-            let message = "No sky box found";
-            spinner.finish_with_message(message);
-            return Err(message.into());
-        }
+    /**
+     * Find a box again, by its UDN or serial number, e.g. after DHCP gives it another IP address
+     */
+    pub async fn rediscover(&self, skybox: &SkyBox) -> Result<SkyBox> {
+        let device = skybox.device.clone()
+            .ok_or_else(|| format!("Cannot recognise {} as its identity is unknown, use subcommand `scan`", skybox))?;
 
-        spinner.finish_with_message(format!("Found {} skybox", boxes.len()).as_str());
-        for (i,skybox) in boxes.iter().enumerate() {
-            println!("{}:\t{}", i, skybox);
-        }
-        eprint!("Choose a skybox: ");
-
-        let line = match io::stdin().lock().lines().next() {
-            None => panic!("Line cannot be empty"),
-            Some(result) => {
-                match result {
-                    Err(err) => panic!("Cannot read input: {}", err),
-                    Ok(line) => line
-                }
+        Selection::Device(device).select(self.discover().await?)
+    }
+
+    /**
+     * Run `operation` on the box saved as `entry`. If the box cannot be reached, find it again, pass it to `found`,
+     * e.g. to save its new address, and retry there.
+     */
+    pub async fn with_rediscovery<T, F, Fut, G>(&self, entry: &BoxEntry, operation: F, found: G) -> Result<T>
+        where F: Fn(SkyBox) -> Fut,
+              Fut: Future<Output = Result<T>>,
+              G: FnOnce(&SkyBox) -> Result<()>
+    {
+        let error = match operation(entry.skybox()?).await {
+            Err(error) if error.is_connection_failure() => error,
+            result => return result
+        };
+
+        let skybox = match self.rediscover(&entry.skybox()?).await {
+            Ok(skybox) => skybox,
+            Err(rediscovery_error) => {
+                let message = format!("{}, and cannot find it again: {}", error, rediscovery_error);
+                return Err(error).chain_err(|| message);
             }
         };
 
-        let line_number: usize = line.parse()?;
-        let skybox = &boxes.get(line_number).ok_or("Not an existant skybox")?;
-        println!("Using {}", skybox);
+        found(&skybox)?;
+        operation(skybox).await
+    }
+
+    /**
+     * Find the SkyBox at `address`, e.g. `192.168.2.152` or `192.168.2.152:49153`, without SSDP.
+     * Probes the description documents at the known ports, or only at the port given, all at once.
+     */
+    pub async fn add(&self, address: &str) -> Result<SkyBox> {
+        let root_url = Url::parse(&format!("http://{}/", address))
+            .map_err(|error| format!("Bad skybox address `{}`: {}", address, error))?;
+        let ports = match given_port(address) {
+            Some(port) => vec![port],
+            None => DESCRIPTION_PORTS.to_vec()
+        };
+
+        let mut locations = Vec::new();
+        for port in ports {
+            for path in DESCRIPTION_PATHS.iter() {
+                let mut location = root_url.clone();
+                location.set_port(Some(port)).map_err(|_| format!("Bad skybox address `{}`", address))?;
+                location.set_path(path);
+                locations.push(location);
+            }
+        }
+
+        self.find(&locations, PROBE_TIMEOUT).await
+            .ok_or_else(|| format!("Cannot find a skybox at {}", address).into())
+    }
 
-        // Store the user's preferences
-        skybox.save_box()
+    /**
+     * Find the SkyBox described at `locations`, e.g. the `LOCATION`s a box announced, without SSDP
+     */
+    pub async fn add_from_locations(&self, locations: &[Url]) -> Result<SkyBox> {
+        self.find(locations, DESCRIPTION_TIMEOUT).await
+            .ok_or_else(|| format!("Cannot find a skybox described at {}", locations.iter().map(Url::as_str).collect::<Vec<_>>().join(", ")).into())
+    }
+
+    /**
+     * Fetch every description at once, and merge them into a SkyBox, preferring earlier locations
+     */
+    async fn find(&self, locations: &[Url], timeout: Duration) -> Option<SkyBox> {
+        let descriptions = join_all(locations.iter().map(|location| self.describe(location, timeout))).await;
+        let mut found = Description::default();
+        for (_, description) in descriptions.into_iter().flatten() {
+            found.merge(description);
+            if let Some(skybox) = found.skybox() {
+                return Some(skybox);
+            }
+        }
+        None
     }
 
-    async fn get_service_url(&self, urn: &URN, location: &Url) -> Result<Url> {
-        let client = reqwest::Client::new();
-        let resp = client.get(location.clone())
+    /**
+     * Fetch a device description, giving the host of its SkyBox services, their URLs and the identity of the device
+     */
+    async fn describe(&self, location: &Url, timeout: Duration) -> Result<(String, Description)> {
+        let resp = self.http.get(location.clone())
             .header("user-agent", "SKY_skyplus")
+            .timeout(timeout)
             .send().await?
+            .error_for_status()?
             .text().await?;
-
         let doc = roxmltree::Document::parse(&resp)?;
 
-        self.extract_service_url(&doc, urn, &location)
+        let description = Description {
+            play_url: self.extract_service_url(&doc, &SKY_PLAY, location).ok(),
+            browse_url: self.extract_service_url(&doc, &SKY_BROWSE, location).ok(),
+            device: Some(DeviceInfo::parse(&doc))
+        };
+        if description.play_url.is_none() && description.browse_url.is_none() {
+            return Err(format!("Offers neither {} nor {}", SKY_PLAY, SKY_BROWSE).into());
+        }
+
+        let host = location.host_str().ok_or("Absent host component from URL")?.to_owned();
+        Ok((host, description))
     }
 
     // Get XPath /root/device/serviceList/service[serviceType/text()='${serviceType}']/controlURL/text()
@@ -131,25 +351,234 @@ impl Scanner {
         Ok(result)
     }
 
-    /**
-     * SSDP scan.
-     * Get the descriptor document for each response.
-     * @return a map of <IP-Address, ServiceURL>
-     */
-    async fn ssdp_search(&self, st: &SearchTarget, urn: &URN) -> Result<HashMap<String, Url>> {
-        let mut result: HashMap<String, Url> = HashMap::new();
+}
+
+/**
+ * The port given in an address such as `192.168.2.152:80`, even the default HTTP port that `Url::port` leaves out
+ */
+fn given_port(address: &str) -> Option<u16> {
+    address.rsplit_once(':').and_then(|(_, port)| port.parse().ok())
+}
 
-        let mut responses = ssdp_client::search(st, TIMEOUT, 2).await?;
-        while let Some(response) = responses.next().await {
-            let location = Url::parse(response?.location())?;
-            let browse_url = self.get_service_url(urn, &location).await?;
+/**
+ * The description URL of each device that responded, once each, though it may respond more than once.
+ * Devices with several network interfaces are recognised by the UDN of their USN, whichever service answered:
+ * only the first host to answer for a UDN is kept.
+ */
+fn unique_locations(responses: &[Message]) -> Vec<String> {
+    let mut locations: Vec<String> = Vec::new();
+    let mut hosts_by_udn: Vec<(&str, Option<String>)> = Vec::new();
 
-            result.insert(
-                browse_url.host_str().ok_or("Absent host component from URL")?.into(),
-                browse_url);
+    for response in responses {
+        let location = match response.header("LOCATION") {
+            Some(location) => location,
+            None => continue
+        };
+        let host = Url::parse(location).ok().and_then(|url| url.host_str().map(String::from));
+        let elsewhere = response.udn().iter().any(|udn|
+            hosts_by_udn.iter().any(|(other_udn, other_host)| other_udn == udn && *other_host != host));
+        if elsewhere || locations.iter().any(|other| other == location) {
+            continue;
         }
 
-        Ok(result)
+        locations.push(location.into());
+        if let Some(udn) = response.udn() {
+            hosts_by_udn.push((udn, host));
+        }
     }
 
-}
\ No newline at end of file
+    locations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, RECORDINGS, skybox_at, switched_off};
+
+    fn boxes() -> Vec<SkyBox> {
+        ["192.168.2.15", "192.168.2.152"].iter()
+            .map(|host| skybox_at(host)
+                .with_device(DeviceInfo { udn: Some(format!("uuid:{}", host)), ..DeviceInfo::default() }))
+            .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let host_of = |skybox: SkyBox| skybox.browse_url.host_str().unwrap().to_owned();
+
+        assert_eq!("192.168.2.15", host_of(Selection::First.select(boxes()).unwrap()));
+        assert_eq!("192.168.2.152", host_of(Selection::Index(1).select(boxes()).unwrap()));
+        assert_eq!("192.168.2.152", host_of(Selection::Host("192.168.2.152".into()).select(boxes()).unwrap()));
+
+        assert!(Selection::First.select(vec![]).is_err());
+        assert!(Selection::Index(2).select(boxes()).is_err());
+        assert!(Selection::Host("192.168.2.1".into()).select(boxes()).is_err());
+
+        let device = |udn: &str| DeviceInfo { udn: Some(udn.into()), ..DeviceInfo::default() };
+        assert_eq!("192.168.2.152", host_of(Selection::Device(device("uuid:192.168.2.152")).select(boxes()).unwrap()));
+        assert!(Selection::Device(device("uuid:192.168.2.1")).select(boxes()).is_err());
+    }
+
+    fn simulated_scanner(simulator: &Simulator) -> Scanner {
+        Scanner::new()
+            .with_target(simulator.ssdp_addr())
+            .with_timeout(Duration::from_millis(500))
+    }
+
+    #[tokio::test]
+    async fn test_discover() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+
+        let boxes = simulated_scanner(&simulator).with_repeats(2).discover().await.unwrap();
+
+        assert_eq!(1, boxes.len());
+        assert_eq!(simulator.play_url(), boxes[0].play_url);
+        assert_eq!(simulator.browse_url(), boxes[0].browse_url);
+        assert_eq!("Simulated Sky+HD at 127.0.0.1", boxes[0].to_string());
+    }
+
+    fn response(location: &str, usn: &str) -> Message {
+        Message::parse(&format!("HTTP/1.1 200 OK\r\nLOCATION: {}\r\nST: {}\r\nUSN: {}\r\n\r\n", location, SKY_BROWSE, usn)).unwrap()
+    }
+
+    #[test]
+    fn test_unique_locations() {
+        let (play, browse) = (SKY_PLAY.to_string(), SKY_BROWSE.to_string());
+        let responses = vec![
+            response("http://192.168.2.152:49153/description0.xml", &format!("uuid:BEEF::{}", play)),
+            response("http://192.168.2.152:49153/description0.xml", &format!("uuid:BEEF::{}", browse)),
+            response("http://192.168.2.15:49153/description0.xml", &format!("uuid:CAFE::{}", browse)),
+            response("http://10.0.0.15:49153/description0.xml", &format!("uuid:CAFE::{}", browse)),
+        ];
+
+        assert_eq!(vec!["http://192.168.2.152:49153/description0.xml", "http://192.168.2.15:49153/description0.xml"],
+            unique_locations(&responses));
+
+        // Both services of a box on two interfaces, answering in any order
+        let responses = vec![
+            response("http://192.168.2.15:49153/description0.xml", &format!("uuid:CAFE::{}", play)),
+            response("http://10.0.0.15:49153/description1.xml", &format!("uuid:CAFE::{}", browse)),
+            response("http://192.168.2.15:49153/description1.xml", &format!("uuid:CAFE::{}", browse)),
+            response("http://10.0.0.15:49153/description0.xml", &format!("uuid:CAFE::{}", play)),
+        ];
+
+        assert_eq!(vec!["http://192.168.2.15:49153/description0.xml", "http://192.168.2.15:49153/description1.xml"],
+            unique_locations(&responses));
+    }
+
+    #[tokio::test]
+    async fn test_report_failures() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let responses = vec![
+            response("http://127.0.0.1:9/description0.xml", "uuid:1"),
+            response(simulator.description_url().as_str(), "uuid:2"),
+            response(simulator.description_url().as_str(), "uuid:3"),
+            response("not a URL", "uuid:4"),
+        ];
+
+        let report = Scanner::new().report(&responses).await;
+
+        assert_eq!(4, report.responses);
+        assert_eq!(vec![simulator.browse_url()], report.boxes.iter().map(|skybox| skybox.browse_url.clone()).collect::<Vec<_>>());
+        assert_eq!(vec!["http://127.0.0.1:9/description0.xml", "not a URL"],
+            report.diagnostics.iter().map(|diagnostic| diagnostic.location.as_str()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_scan_report() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+
+        let report = simulated_scanner(&simulator).scan_report().await.unwrap();
+
+        assert_eq!(2, report.responses);
+        assert_eq!(1, report.boxes.len());
+        assert!(report.diagnostics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rediscover() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let scanner = simulated_scanner(&simulator);
+
+        let moved = skybox_at("192.168.2.15");
+        assert!(scanner.rediscover(&moved).await.is_err());
+
+        let moved = moved.with_device(DeviceInfo { serial_number: Some("0000000000BEEF".into()), ..DeviceInfo::default() });
+        assert_eq!(simulator.browse_url(), scanner.rediscover(&moved).await.unwrap().browse_url);
+    }
+
+    #[tokio::test]
+    async fn test_with_rediscovery() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let scanner = simulated_scanner(&simulator);
+        let moved = switched_off();
+        let list = |skybox: SkyBox| async move { skybox.list_items().await };
+
+        let lost = BoxEntry::new("lounge".into(), &moved);
+        let error = scanner.with_rediscovery(&lost, list, |_| panic!("Not found")).await.unwrap_err();
+        assert!(error.to_string().contains("cannot find it again"));
+
+        let entry = BoxEntry::new("lounge".into(), &moved.with_device(DeviceInfo { serial_number: Some("0000000000BEEF".into()), ..DeviceInfo::default() }));
+        let mut found = None;
+        let listing = scanner.with_rediscovery(&entry, list, |skybox| {
+            found = Some(skybox.browse_url.clone());
+            Ok(())
+        }).await.unwrap();
+
+        assert_eq!(Some(simulator.browse_url()), found);
+        assert_eq!(4, listing.items.len());
+    }
+
+    #[tokio::test]
+    async fn test_describe() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let scanner = Scanner::new();
+
+        let (host, description) = scanner.describe(&simulator.description_url(), DESCRIPTION_TIMEOUT).await.unwrap();
+
+        assert_eq!("127.0.0.1", host);
+        assert_eq!(Some(simulator.play_url()), description.play_url);
+        assert_eq!(Some(simulator.browse_url()), description.browse_url);
+        assert_eq!(Some("0000000000BEEF"), description.device.and_then(|device| device.serial_number).as_deref());
+    }
+
+    #[test]
+    fn test_given_port() {
+        assert_eq!(Some(80), given_port("192.168.2.152:80"));
+        assert_eq!(Some(49153), given_port("192.168.2.152:49153"));
+        assert_eq!(Some(80), given_port("[::1]:80"));
+        assert_eq!(None, given_port("192.168.2.152"));
+        assert_eq!(None, given_port("[::1]"));
+        assert_eq!(None, given_port("skybox.local"));
+    }
+
+    #[tokio::test]
+    async fn test_add() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let scanner = Scanner::new();
+        let address = format!("127.0.0.1:{}", simulator.description_url().port().unwrap());
+
+        let skybox = scanner.add(&address).await.unwrap();
+        assert_eq!(simulator.play_url(), skybox.play_url);
+        assert_eq!(simulator.browse_url(), skybox.browse_url);
+        assert_eq!("Simulated Sky+HD at 127.0.0.1", skybox.to_string());
+
+        // Nothing listening on the port
+        assert!(scanner.add("127.0.0.1:9").await.is_err());
+        assert!(scanner.add("not an address").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_from_locations() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let scanner = Scanner::new();
+
+        let skybox = scanner.add_from_locations(&[simulator.description_url()]).await.unwrap();
+        assert_eq!(simulator.browse_url(), skybox.browse_url);
+        assert_eq!(Some("0000000000BEEF"), skybox.device.and_then(|device| device.serial_number).as_deref());
+
+        let mut elsewhere = simulator.description_url();
+        elsewhere.set_path("/elsewhere.xml");
+        assert!(scanner.add_from_locations(&[elsewhere]).await.is_err());
+    }
+}
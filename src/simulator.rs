@@ -0,0 +1,605 @@
+use super::common::{SKY_BROWSE, SKY_PLAY};
+use super::common::errors::*;
+use super::content::{RECORDINGS_CONTAINER, ROOT_CONTAINER};
+use super::item::Item;
+use super::skybox::SkyBox;
+use super::soap::{envelope, escape};
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use url::Url;
+
+/**
+ * DIDL-Lite recordings used to seed a simulator, as found in the wild
+ */
+pub const RECORDINGS: &str = include_str!("fixtures/recordings.xml");
+
+const DESCRIPTION_PATH: &str = "/description0.xml";
+const PLAY_CONTROL_PATH: &str = "/SkyPlay2";
+const BROWSE_CONTROL_PATH: &str = "/SkyBrowse2";
+
+const DIDL_LITE_OPEN: &str = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" xmlns:vx="urn:schemas-nds-com:metadata-1-0" xmlns:dc="http://purl.org/dc/elements/1.1/">"#;
+const DIDL_LITE_CLOSE: &str = "</DIDL-Lite>";
+
+const SORT_CAPABILITIES: &[&str] = &["dc:title", "upnp:recordedStartDateTime"];
+const SERVER: &str = "Linux/2.6 UPnP/1.0 SKY DLNADOC/1.50";
+const UDN: &str = "uuid:444D5376-3247-536B-7953-00000000BEEF";
+
+/**
+ * The mutable state of a simulated SkyBox
+ */
+#[derive(Default)]
+struct State {
+    recordings: Vec<(String, String)>, // (ID, <item> XML)
+    update_id: usize,
+    now_playing: Option<String>
+}
+
+/**
+ * A stand-in for a SkyPlus box, for use in tests.
+ *
+ * Serves a UPnP device description, the `SkyBrowse:2` and `SkyPlay:2` control endpoints,
+ * answers SSDP M-SEARCH requests sent to its SSDP address, and announces itself with SSDP NOTIFY messages.
+ */
+pub struct Simulator {
+    http_addr: SocketAddr,
+    ssdp_addr: SocketAddr,
+    state: Arc<Mutex<State>>
+}
+
+impl Simulator {
+
+    /**
+     * Start a simulator on ephemeral localhost ports, holding the `<item>`s of a DIDL-Lite document
+     */
+    pub async fn start(didl_lite: &str) -> Result<Simulator> {
+        let state = Arc::new(Mutex::new(State {
+            recordings: parse_recordings(didl_lite)?,
+            ..State::default()
+        }));
+
+        let server = Server::try_bind(&([127, 0, 0, 1], 0).into())
+            .map_err(|error| format!("Cannot start simulator: {}", error))?;
+
+        let http_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = http_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request)))
+            }
+        });
+
+        let server = server.serve(make_service);
+        let http_addr = server.local_addr();
+        tokio::spawn(server);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let ssdp_addr = socket.local_addr()?;
+        tokio::spawn(answer_searches(socket, http_addr));
+
+        Ok(Simulator { http_addr, ssdp_addr, state })
+    }
+
+    /**
+     * The URL of the UPnP device description, as it would appear in an SSDP `LOCATION` header
+     */
+    pub fn description_url(&self) -> Url {
+        Url::parse(&format!("http://{}{}", self.http_addr, DESCRIPTION_PATH)).expect("Cannot build description URL")
+    }
+
+    pub fn play_url(&self) -> Url {
+        Url::parse(&format!("http://{}{}", self.http_addr, PLAY_CONTROL_PATH)).expect("Cannot build play URL")
+    }
+
+    pub fn browse_url(&self) -> Url {
+        Url::parse(&format!("http://{}{}", self.http_addr, BROWSE_CONTROL_PATH)).expect("Cannot build browse URL")
+    }
+
+    /**
+     * A SkyBox using this simulator
+     */
+    pub fn skybox(&self) -> SkyBox {
+        SkyBox::new(self.play_url(), self.browse_url())
+    }
+
+    /**
+     * The address answering SSDP M-SEARCH requests
+     */
+    pub fn ssdp_addr(&self) -> SocketAddr {
+        self.ssdp_addr
+    }
+
+    /**
+     * IDs of the recordings currently held
+     */
+    pub fn recording_ids(&self) -> Vec<String> {
+        let state = self.state.lock().expect("Simulator state poisoned");
+        state.recordings.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /**
+     * Send SSDP NOTIFY messages for both services to `to`, announcing `ssdp:alive`, or else `ssdp:byebye`
+     */
+    pub async fn announce(&self, to: SocketAddr, alive: bool) -> Result<()> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        for notification in notifications(self.http_addr, alive) {
+            socket.send_to(notification.as_bytes(), to).await?;
+        }
+        Ok(())
+    }
+
+    /**
+     * The URI most recently passed to `SetAVTransportURI`
+     */
+    pub fn now_playing(&self) -> Option<String> {
+        self.state.lock().expect("Simulator state poisoned").now_playing.clone()
+    }
+}
+
+/**
+ * A SkyBox at `host`, on the port and paths of a real box
+ */
+pub fn skybox_at(host: &str) -> SkyBox {
+    SkyBox::new(
+        Url::parse(&format!("http://{}:49153{}", host, PLAY_CONTROL_PATH)).unwrap(),
+        Url::parse(&format!("http://{}:49153{}", host, BROWSE_CONTROL_PATH)).unwrap())
+}
+
+/**
+ * A SkyBox at a port nothing listens on, as if switched off
+ */
+pub fn switched_off() -> SkyBox {
+    SkyBox::new(
+        Url::parse(&format!("http://127.0.0.1:9{}", PLAY_CONTROL_PATH)).unwrap(),
+        Url::parse(&format!("http://127.0.0.1:9{}", BROWSE_CONTROL_PATH)).unwrap())
+}
+
+/**
+ * Every recording in `RECORDINGS`, parsed
+ */
+pub fn items() -> Vec<Item> {
+    let doc = roxmltree::Document::parse(RECORDINGS).unwrap();
+    doc.descendants()
+        .filter(|n| n.tag_name().name() == "item")
+        .map(|n| Item::build(n).unwrap())
+        .collect()
+}
+
+/**
+ * The first recording in `RECORDINGS`, parsed
+ */
+pub fn first_item() -> Item {
+    with_first_item(RECORDINGS, |elem| Item::build(elem).unwrap())
+}
+
+/**
+ * Parse the first `<item>` of a DIDL-Lite document with `build`, e.g. `Item::build_lenient`
+ */
+pub fn with_first_item<T>(didl_lite: &str, build: impl FnOnce(roxmltree::Node) -> T) -> T {
+    let doc = roxmltree::Document::parse(didl_lite).unwrap();
+    build(doc.root_element().first_element_child().unwrap())
+}
+
+fn parse_recordings(didl_lite: &str) -> Result<Vec<(String, String)>> {
+    let doc = roxmltree::Document::parse(didl_lite)?;
+
+    doc.descendants()
+        .filter(|n| n.tag_name().name() == "item")
+        .map(|n| {
+            let id = n.attribute("id").ok_or("Field `id` is absent")?;
+            Ok((id.into(), didl_lite[n.range()].into()))
+        })
+        .collect()
+}
+
+//=========== HTTP
+
+async fn handle(state: Arc<Mutex<State>>, request: Request<Body>) -> std::result::Result<Response<Body>, hyper::Error> {
+    let path = request.uri().path().to_owned();
+
+    if request.method() == Method::GET && path == DESCRIPTION_PATH {
+        return Ok(xml_response(StatusCode::OK, description()));
+    }
+
+    if request.method() != Method::POST {
+        return Ok(xml_response(StatusCode::NOT_FOUND, String::new()));
+    }
+
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let body = String::from_utf8_lossy(&body);
+
+    let response = match parse_action(&body) {
+        Ok((action, arguments)) => {
+            let mut state = state.lock().expect("Simulator state poisoned");
+            match (path.as_str(), action.as_str()) {
+                (BROWSE_CONTROL_PATH, "Browse") => browse(&state, &arguments),
+                (BROWSE_CONTROL_PATH, "DestroyObject") => destroy_object(&mut state, &arguments),
+                (PLAY_CONTROL_PATH, "SetAVTransportURI") => set_av_transport_uri(&mut state, &arguments),
+                _ => fault(401, "Invalid Action")
+            }
+        },
+        Err(_) => fault(402, "Invalid Args")
+    };
+
+    Ok(response)
+}
+
+/**
+ * Get the action name and arguments from a SOAP request envelope
+ */
+fn parse_action(body: &str) -> Result<(String, HashMap<String, String>)> {
+    let doc = roxmltree::Document::parse(body)?;
+
+    let body_elem = doc.descendants()
+        .find(|n| n.tag_name().name() == "Body")
+        .ok_or("Cannot find `Body` element")?;
+    let action_elem = body_elem.first_element_child().ok_or("Absent action element")?;
+
+    let arguments = action_elem.children()
+        .filter(|n| n.is_element())
+        .map(|n| (n.tag_name().name().into(), n.text().unwrap_or_default().into()))
+        .collect();
+
+    Ok((action_elem.tag_name().name().into(), arguments))
+}
+
+fn browse(state: &State, arguments: &HashMap<String, String>) -> Response<Body> {
+    let object_id = arguments.get("ObjectID").map(String::as_str).unwrap_or_default();
+    let filter = arguments.get("Filter").map(String::as_str).unwrap_or("*");
+
+    let mut objects: Vec<String> = match arguments.get("BrowseFlag").map(String::as_str) {
+        Some("BrowseMetadata") => match object_id {
+            ROOT_CONTAINER => vec![root_container()],
+            RECORDINGS_CONTAINER => vec![recordings_container(state)],
+            _ => match state.recordings.iter().find(|(id, _)| id == object_id) {
+                Some((_, xml)) => vec![xml.clone()],
+                None => return fault(701, "No such object")
+            }
+        },
+        Some("BrowseDirectChildren") => match object_id {
+            ROOT_CONTAINER => vec![recordings_container(state)],
+            RECORDINGS_CONTAINER => state.recordings.iter().map(|(_, xml)| xml.clone()).collect(),
+            _ if state.recordings.iter().any(|(id, _)| id == object_id) => return fault(710, "No such container"),
+            _ => return fault(701, "No such object")
+        },
+        _ => return fault(402, "Invalid Args")
+    };
+
+    let starting_index: usize = match arguments.get("StartingIndex").map(|s| s.parse()) {
+        Some(Ok(index)) => index,
+        _ => return fault(402, "Invalid Args")
+    };
+    let requested_count: usize = match arguments.get("RequestedCount").map(|s| s.parse()) {
+        Some(Ok(0)) => objects.len(), // Zero means everything
+        Some(Ok(count)) => count,
+        _ => return fault(402, "Invalid Args")
+    };
+
+    let sort_criteria = arguments.get("SortCriteria").map(String::as_str).unwrap_or_default();
+    if sort_objects(&mut objects, sort_criteria).is_err() {
+        return fault(709, "Unsupported or invalid sort criteria");
+    }
+
+    let page: Vec<_> = objects.iter()
+        .skip(starting_index)
+        .take(requested_count)
+        .map(|xml| filter_properties(xml, filter))
+        .collect();
+
+    let result = format!("{}{}{}", DIDL_LITE_OPEN, page.join(""), DIDL_LITE_CLOSE);
+
+    action_response(&SKY_BROWSE.to_string(), "Browse", &[
+        ("Result", &escape(&result)),
+        ("NumberReturned", &page.len().to_string()),
+        ("TotalMatches", &objects.len().to_string()),
+        ("UpdateID", &state.update_id.to_string())
+    ])
+}
+
+fn root_container() -> String {
+    format!(r#"<container id="{}" parentID="-1" restricted="1" childCount="1"><dc:title>root</dc:title><upnp:class>object.container</upnp:class></container>"#,
+        ROOT_CONTAINER)
+}
+
+fn recordings_container(state: &State) -> String {
+    format!(r#"<container id="{}" parentID="{}" restricted="1" childCount="{}"><dc:title>Planner</dc:title><upnp:class>object.container</upnp:class></container>"#,
+        RECORDINGS_CONTAINER, ROOT_CONTAINER, state.recordings.len())
+}
+
+/**
+ * Sort DIDL-Lite objects by comma separated properties, each prefixed by `+` or `-`, failing on any not in `SORT_CAPABILITIES`
+ */
+fn sort_objects(objects: &mut [String], sort_criteria: &str) -> Result<()> {
+    let mut keys = Vec::new();
+    for criterion in sort_criteria.split(',').map(str::trim).filter(|criterion| !criterion.is_empty()) {
+        let (descending, property) = match criterion.strip_prefix('-') {
+            Some(property) => (true, property),
+            None => (false, criterion.trim_start_matches('+'))
+        };
+        if !SORT_CAPABILITIES.contains(&property) {
+            return Err(format!("Cannot sort by {}", property).into());
+        }
+        keys.push((descending, property));
+    }
+
+    let value_of = |xml: &str, property: &str| -> Option<String> {
+        let didl_lite = format!("{}{}{}", DIDL_LITE_OPEN, xml, DIDL_LITE_CLOSE);
+        let doc = roxmltree::Document::parse(&didl_lite).ok()?;
+        let name = property.rsplit(':').next().unwrap_or(property);
+        let text = doc.descendants().find(|n| n.tag_name().name() == name)?.text()?.to_owned();
+        Some(text)
+    };
+
+    objects.sort_by(|a, b| keys.iter()
+        .map(|(descending, property)| {
+            let ordering = value_of(a, property).cmp(&value_of(b, property));
+            if *descending { ordering.reverse() } else { ordering }
+        })
+        .find(|ordering| *ordering != std::cmp::Ordering::Equal)
+        .unwrap_or(std::cmp::Ordering::Equal));
+    Ok(())
+}
+
+/**
+ * Leave out the child elements of an `<item>` or `<container>` not named in a `Filter`, such as `dc:title,res@size`.
+ * Attributes are always kept.
+ */
+fn filter_properties(xml: &str, filter: &str) -> String {
+    let properties: Vec<&str> = filter.split(',')
+        .map(|property| property.trim().split('@').next().unwrap_or_default())
+        .collect();
+    if properties.contains(&"*") {
+        return xml.into();
+    }
+
+    let didl_lite = format!("{}{}{}", DIDL_LITE_OPEN, xml, DIDL_LITE_CLOSE);
+    let doc = match roxmltree::Document::parse(&didl_lite) {
+        Ok(doc) => doc,
+        Err(_) => return xml.into()
+    };
+    let children: String = doc.root_element().first_element_child()
+        .into_iter()
+        .flat_map(|object| object.children().filter(|n| n.is_element()))
+        .map(|n| &didl_lite[n.range()])
+        .filter(|child| properties.contains(&qualified_name(child)))
+        .collect();
+
+    let start_tag_len = xml.find('>').map_or(xml.len(), |index| index + 1);
+    format!("{}{}</{}>", &xml[..start_tag_len], children, qualified_name(xml))
+}
+
+/**
+ * The name of an element, with any prefix, from its XML, e.g. `dc:title` from `<dc:title>...`
+ */
+fn qualified_name(xml: &str) -> &str {
+    xml.trim_start_matches('<')
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default()
+}
+
+fn destroy_object(state: &mut State, arguments: &HashMap<String, String>) -> Response<Body> {
+    let object_id = arguments.get("ObjectID").map(String::as_str).unwrap_or_default();
+
+    match state.recordings.iter().position(|(id, _)| id == object_id) {
+        Some(index) => {
+            state.recordings.remove(index);
+            state.update_id += 1;
+            action_response(&SKY_BROWSE.to_string(), "DestroyObject", &[])
+        },
+        None => fault(701, "No such object")
+    }
+}
+
+fn set_av_transport_uri(state: &mut State, arguments: &HashMap<String, String>) -> Response<Body> {
+    let uri = match arguments.get("CurrentURI") {
+        Some(uri) if !uri.is_empty() => uri,
+        _ => return fault(402, "Invalid Args")
+    };
+
+    // Only the resources of held recordings can be played
+    let res = uri.split('?').next().unwrap_or_default();
+    let res_elem = format!(">{}</res>", res);
+    if !state.recordings.iter().any(|(_, xml)| xml.contains(&res_elem)) {
+        return fault(716, "Resource not found");
+    }
+
+    state.now_playing = Some(uri.clone());
+    action_response(&SKY_PLAY.to_string(), "SetAVTransportURI", &[])
+}
+
+fn action_response(service_type: &str, action: &str, arguments: &[(&str, &str)]) -> Response<Body> {
+    let arguments: String = arguments.iter()
+        .map(|(key, value)| format!("<{}>{}</{}>", key, value, key))
+        .collect();
+
+    xml_response(StatusCode::OK, envelope(&format!(
+        r#"<u:{}Response xmlns:u="{}">{}</u:{}Response>"#,
+        action, service_type, arguments, action)))
+}
+
+fn fault(error_code: u32, error_description: &str) -> Response<Body> {
+    xml_response(StatusCode::INTERNAL_SERVER_ERROR, envelope(&format!(
+        r#"<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError></detail></s:Fault>"#,
+        error_code, error_description)))
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", r#"text/xml; charset="utf-8""#)
+        .header("Server", SERVER)
+        .body(Body::from(body))
+        .expect("Cannot build response")
+}
+
+fn description() -> String {
+    format!(r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+    <specVersion><major>1</major><minor>0</minor></specVersion>
+    <device>
+        <deviceType>urn:schemas-nds-com:device:SkyServe:2</deviceType>
+        <friendlyName>Simulated Sky+HD</friendlyName>
+        <manufacturer>Sky</manufacturer>
+        <modelName>DRX890</modelName>
+        <modelNumber>4F31H7</modelNumber>
+        <serialNumber>0000000000BEEF</serialNumber>
+        <UDN>{udn}</UDN>
+        <serviceList>
+            <service>
+                <serviceType>{play}</serviceType>
+                <serviceId>urn:nds-com:serviceId:SkyPlay</serviceId>
+                <SCPDURL>/SkyPlay2.xml</SCPDURL>
+                <controlURL>{play_control}</controlURL>
+                <eventSubURL>/SkyPlay2/events</eventSubURL>
+            </service>
+            <service>
+                <serviceType>{browse}</serviceType>
+                <serviceId>urn:nds-com:serviceId:SkyBrowse</serviceId>
+                <SCPDURL>/SkyBrowse2.xml</SCPDURL>
+                <controlURL>{browse_control}</controlURL>
+                <eventSubURL>/SkyBrowse2/events</eventSubURL>
+            </service>
+        </serviceList>
+    </device>
+</root>"#,
+        udn = UDN,
+        play = SKY_PLAY,
+        play_control = PLAY_CONTROL_PATH,
+        browse = SKY_BROWSE,
+        browse_control = BROWSE_CONTROL_PATH)
+}
+
+//=========== SSDP
+
+async fn answer_searches(socket: UdpSocket, http_addr: SocketAddr) {
+    let mut buffer = [0u8; 2048];
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(_) => return
+        };
+
+        for response in search_responses(&String::from_utf8_lossy(&buffer[..len]), http_addr) {
+            if socket.send_to(response.as_bytes(), peer).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/**
+ * Build the responses to one M-SEARCH datagram: one per matching service
+ */
+fn search_responses(request: &str, http_addr: SocketAddr) -> Vec<String> {
+    let mut lines = request.lines();
+    if lines.next().map(str::trim) != Some("M-SEARCH * HTTP/1.1") {
+        return vec![];
+    }
+
+    let search_target = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("ST"))
+        .map(|(_, value)| value.trim().to_owned())
+        .unwrap_or_default();
+
+    let (play, browse) = (SKY_PLAY.to_string(), SKY_BROWSE.to_string());
+    let matching: Vec<&String> = match search_target.as_str() {
+        "ssdp:all" => vec![&play, &browse],
+        target if target == play => vec![&play],
+        target if target == browse => vec![&browse],
+        _ => vec![]
+    };
+
+    matching.into_iter()
+        .map(|service_type| format!(
+            "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nEXT:\r\nLOCATION: http://{}{}\r\nSERVER: {}\r\nST: {}\r\nUSN: {}::{}\r\n\r\n",
+            http_addr, DESCRIPTION_PATH, SERVER, service_type, UDN, service_type))
+        .collect()
+}
+
+/**
+ * Build the NOTIFY messages for each service
+ */
+fn notifications(http_addr: SocketAddr, alive: bool) -> Vec<String> {
+    [SKY_PLAY.to_string(), SKY_BROWSE.to_string()].iter()
+        .map(|service_type| if alive {
+            format!(
+                "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCACHE-CONTROL: max-age=1800\r\nLOCATION: http://{}{}\r\nNT: {}\r\nNTS: ssdp:alive\r\nSERVER: {}\r\nUSN: {}::{}\r\n\r\n",
+                http_addr, DESCRIPTION_PATH, service_type, SERVER, UDN, service_type)
+        } else {
+            format!(
+                "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT: {}\r\nNTS: ssdp:byebye\r\nUSN: {}::{}\r\n\r\n",
+                service_type, UDN, service_type)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m_search(search_target: &str) -> String {
+        format!("M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n", search_target)
+    }
+
+    #[test]
+    fn test_parse_recordings() {
+        let recordings = parse_recordings(RECORDINGS).unwrap();
+
+        assert_eq!(4, recordings.len());
+        assert_eq!("BOOK:687878212", recordings[0].0);
+        assert!(recordings[0].1.starts_with("<item "));
+        assert!(recordings[0].1.ends_with("</item>"));
+    }
+
+    #[test]
+    fn test_search_responses() {
+        let http_addr: SocketAddr = ([127, 0, 0, 1], 49153).into();
+
+        let responses = search_responses(&m_search(&SKY_BROWSE.to_string()), http_addr);
+        assert_eq!(1, responses.len());
+        assert!(responses[0].contains("LOCATION: http://127.0.0.1:49153/description0.xml\r\n"));
+        assert!(responses[0].contains(&format!("ST: {}\r\n", SKY_BROWSE)));
+
+        assert_eq!(2, search_responses(&m_search("ssdp:all"), http_addr).len());
+        assert!(search_responses(&m_search("urn:schemas-upnp-org:device:MediaRenderer:1"), http_addr).is_empty());
+        assert!(search_responses("NOTIFY * HTTP/1.1\r\n\r\n", http_addr).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_answers_m_search() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(m_search(&SKY_PLAY.to_string()).as_bytes(), simulator.ssdp_addr()).await.unwrap();
+
+        let mut buffer = [0u8; 2048];
+        let (len, _) = socket.recv_from(&mut buffer).await.unwrap();
+        let response = String::from_utf8_lossy(&buffer[..len]);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(&format!("LOCATION: {}\r\n", simulator.description_url())));
+    }
+
+    #[tokio::test]
+    async fn test_serves_description() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+
+        let description = reqwest::get(simulator.description_url()).await.unwrap()
+            .text().await.unwrap();
+        let doc = roxmltree::Document::parse(&description).unwrap();
+
+        let control_urls: Vec<_> = doc.descendants()
+            .filter(|n| n.tag_name().name() == "controlURL")
+            .filter_map(|n| n.text())
+            .collect();
+        assert_eq!(vec![PLAY_CONTROL_PATH, BROWSE_CONTROL_PATH], control_urls);
+    }
+}
@@ -1,209 +1,664 @@
-use super::item::Item;
-use super::common::{envelope, as_elements};
-use super::common::errors::Result;
-
-use super::lister;
-use super::lister::Lister;
-
+use super::content::{ContentObject, RECORDINGS_CONTAINER};
+use super::device::DeviceInfo;
+use super::item::{Diagnostic, Item};
+use super::common::{SKY_BROWSE, SKY_PLAY};
+use super::registry::Registry;
+use super::soap;
+use super::sort::Sort;
+use super::common::errors::{Error, ErrorKind, Result};
+
+use futures::prelude::*;
+use futures::stream;
 use std::fmt;
-use maplit::hashmap;
 use preferences::{AppInfo, PreferencesMap, Preferences};
 use reqwest::Url;
 
-const USER_AGENT: &str = "SKY_skyplus";
-const CONTENT_TYPE: &str = r#"text/xml; charset="utf-8""#;
+pub(crate) const APP_INFO: AppInfo = AppInfo{name: "skybox", author: "Martin Cowie"};
+const LEGACY_PREFS_KEY: &str = "skybox/location";
+
+const DEFAULT_PAGE_SIZE: usize = 25;
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/**
+ * Recordings, and the problems met parsing them
+ */
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub items: Vec<Item>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Listing {
+
+    /**
+     * The number of recordings that were fetched, whether parsed or skipped
+     */
+    pub fn fetched(&self) -> usize {
+        self.items.len() + self.diagnostics.iter().filter(|d| d.skipped).count()
+    }
 
-const APP_INFO: AppInfo = AppInfo{name: "skybox", author: "Martin Cowie"};
-const PREFS_KEY: &str = "skybox/location";
+    pub(crate) fn extend(&mut self, other: Listing) {
+        self.items.extend(other.items);
+        self.diagnostics.extend(other.diagnostics);
+    }
+}
 
 #[derive(Debug)]
 pub struct SkyBox {
     pub play_url: Url,
     pub browse_url: Url,
+    pub device: Option<DeviceInfo>,
 
-    client: reqwest::Client
+    client: soap::Client,
+    page_size: usize,
+    concurrency: usize,
+    filter: String,
+    sort: Sort
 }
 
 impl SkyBox {
 
     pub fn new(play_url: Url, browse_url: Url) -> SkyBox {
-        SkyBox{play_url, browse_url, client: reqwest::Client::new()}
+        SkyBox{
+            play_url, browse_url,
+            device: None,
+            client: soap::Client::new(),
+            page_size: DEFAULT_PAGE_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            filter: "*".into(),
+            sort: Sort::default()
+        }
+    }
+
+    /**
+     * Fetch this many recordings per request, 25 by default
+     */
+    pub fn with_page_size(mut self, page_size: usize) -> SkyBox {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /**
+     * Have at most this many requests in flight when listing recordings, 4 by default
+     */
+    pub fn with_concurrency(mut self, concurrency: usize) -> SkyBox {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /**
+     * Ask the box for only these properties of each recording, e.g. `Item::PROPERTIES`, rather than every one
+     */
+    pub fn with_properties(mut self, properties: &[&str]) -> SkyBox {
+        self.filter = properties.join(",");
+        self
+    }
+
+    /**
+     * List recordings in this order. The box sorts them if it can, otherwise they are sorted once all are fetched.
+     */
+    pub fn with_sort(mut self, sort: Sort) -> SkyBox {
+        self.sort = sort;
+        self
     }
 
-    pub fn save_box(&self) -> Result<()> {
-        let mut map: PreferencesMap<String> = PreferencesMap::new();
-        map.insert("play".into(), self.play_url.to_string());
-        map.insert("browse".into(), self.browse_url.to_string());
+    /**
+     * Call the box with this client, e.g. one with its own timeout, retries or logger
+     */
+    pub fn with_client(mut self, client: soap::Client) -> SkyBox {
+        self.client = client;
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceInfo) -> SkyBox {
+        self.device = Some(device);
+        self
+    }
+
+    /**
+     * Register this box, and use it by default. Returns its name.
+     */
+    pub fn save_box(&self) -> Result<String> {
+        self.save_box_as(None, true)
+    }
 
-        map.save(&APP_INFO, PREFS_KEY)
-            .map_err(|error|format!("Cannot save skybox: {}", error).into())
+    /**
+     * Register this box under `name`, using it by default if `make_default`, or if it is the first box
+     */
+    pub fn save_box_as(&self, name: Option<&str>, make_default: bool) -> Result<String> {
+        let mut registry = Registry::load()?;
+        let name = registry.add(self, name)?;
+        if make_default {
+            registry.set_default(&name)?;
+        }
+        registry.save()?;
+
+        Ok(name)
     }
 
+    /**
+     * Load the default box
+     */
     pub fn load_box() -> Result<SkyBox> {
-        let map = PreferencesMap::<String>::load(&APP_INFO, PREFS_KEY)?;
+        Registry::load()?.get(None)
+    }
+
+    /**
+     * Load the single box saved by earlier versions
+     */
+    pub(crate) fn load_legacy_box() -> Result<SkyBox> {
+        let map = PreferencesMap::<String>::load(&APP_INFO, LEGACY_PREFS_KEY)?;
         let play_url = Url::parse(map.get("play").ok_or("Attribute `play` absent")?)?;
         let browse_url = Url::parse(map.get("browse").ok_or("Attribute `play` absent")?)?;
 
         Ok(SkyBox::new(play_url, browse_url))
     }
 
-    pub async fn list_items(&self, matches: &clap::ArgMatches) -> Result<()> {
-        let requested_count: usize = 25;
-        let mut starting_index: usize = 0;
+    /**
+     * List every recording. Recordings that cannot be parsed are skipped, and reported in the diagnostics.
+     */
+    pub async fn list_items(&self) -> Result<Listing> {
+        self.list_items_with_progress(|_, _| ()).await
+    }
 
-        let (_, total_items) = self.fetch_items(0, 0).await?;
-        let mut lister = lister::build_lister(total_items, matches);
+    /**
+     * List every recording, calling `progress` with the count fetched so far and the total after each page.
+     * The first page gives the total, then the other pages are fetched concurrently.
+     */
+    pub async fn list_items_with_progress<F>(&self, progress: F) -> Result<Listing>
+        where F: FnMut(usize, usize)
+    {
+        let (first_page, total_items, sort_criteria) = self.fetch_first_page().await?;
+        self.fetch_rest(first_page, total_items, &sort_criteria, progress).await
+    }
 
-        loop {
-            let (items, _) = self.fetch_items(starting_index, requested_count).await?;
+    /**
+     * Fetch the pages after the first, then sort every recording unless the box sorted them by `sort_criteria`
+     */
+    async fn fetch_rest<F>(&self, first_page: Listing, total_items: usize, sort_criteria: &str, mut progress: F) -> Result<Listing>
+        where F: FnMut(usize, usize)
+    {
+        let mut result = Listing {
+            items: Vec::with_capacity(total_items),
+            diagnostics: Vec::new()
+        };
 
-            lister.list(&items);
+        let first_page_len = first_page.fetched();
+        result.extend(first_page);
+        progress(result.fetched(), total_items);
 
-            if items.len() < requested_count {
-                break;
+        if first_page_len > 0 {
+            let mut pages = stream::iter((first_page_len..total_items).step_by(self.page_size))
+                .map(|starting_index| self.fetch_page(starting_index, self.page_size.min(total_items - starting_index), sort_criteria))
+                .buffered(self.concurrency);
+
+            while let Some(page) = pages.next().await {
+                result.extend(page?);
+                progress(result.fetched(), total_items);
             }
-            starting_index += items.len();
         }
-        lister.close();
 
-        Ok(())
+        if sort_criteria.is_empty() {
+            self.sort.apply(&mut result.items);
+        }
+        Ok(result)
     }
 
-    async fn fetch_items(&self, starting_index: usize, requested_count: usize) -> Result<(Vec<Item>, usize)> {
+    /**
+     * Fetch the first page, sorted by the box if it accepts the sort.
+     * Gives the sort criteria used for every page, empty if the box cannot sort.
+     */
+    async fn fetch_first_page(&self) -> Result<(Listing, usize, String)> {
+        if let Some(sort_criteria) = self.sort.sort_criteria().filter(|sort_criteria| !sort_criteria.is_empty()) {
+            match self.fetch_items(0, self.page_size, &sort_criteria).await {
+                Err(ref error) if rejects_sort(error) => (),
+                result => return result.map(|(page, total_items)| (page, total_items, sort_criteria))
+            }
+        }
 
-        let starting_index = starting_index.to_string();
-        let requested_count = requested_count.to_string();
-        let arguments = hashmap!{
-            "ObjectID" => "3",
-            "BrowseFlag" => "BrowseDirectChildren",
-            "Filter" => "*",
-            "StartingIndex" => starting_index.as_str(),
-            "RequestedCount" => requested_count.as_str(),
-            "SortCriteria" => ""
-        };
+        let (page, total_items) = self.fetch_items(0, self.page_size, "").await?;
+        Ok((page, total_items, String::new()))
+    }
 
-        let arguments = as_elements(&arguments);
+    /**
+     * Every recording, fetched a page at a time as the stream is read.
+     * Recordings that cannot be parsed are skipped: use `pages` for the diagnostics.
+     */
+    pub fn items(&self) -> impl Stream<Item = Result<Item>> + '_ {
+        self.pages()
+            .map_ok(|(listing, _)| stream::iter(listing.items.into_iter().map(Ok)))
+            .try_flatten()
+    }
 
-        let browse_elem = format!(r#"<u:Browse xmlns:u="urn:schemas-nds-com:service:SkyBrowse:2">{}</u:Browse>"#, arguments);
-        let body = envelope(browse_elem.as_str());
+    /**
+     * Every page of recordings, with the total number of recordings, fetched one at a time as the stream is read.
+     * If the box cannot sort them, every recording is fetched and sorted into a single page.
+     */
+    pub fn pages(&self) -> impl Stream<Item = Result<(Listing, usize)>> + '_ {
+        type Paging = (usize, Option<(usize, String)>); // Starting index, and the total and sort criteria once known
+
+        stream::try_unfold((0, None), move |(starting_index, paging): Paging| async move {
+            let (page, total_items, sort_criteria) = match paging {
+                None => {
+                    let (page, total_items, sort_criteria) = self.fetch_first_page().await?;
+                    if sort_criteria.is_empty() && !self.sort.is_empty() {
+                        (self.fetch_rest(page, total_items, "", |_, _| ()).await?, total_items, sort_criteria)
+                    } else {
+                        (page, total_items, sort_criteria)
+                    }
+                }
+                Some((total_items, _)) if starting_index >= total_items => return Ok(None),
+                Some((_, sort_criteria)) => {
+                    let (page, total_items) = self.fetch_items(starting_index, self.page_size, &sort_criteria).await?;
+                    (page, total_items, sort_criteria)
+                }
+            };
+
+            let page_len = page.fetched();
+            if page_len == 0 {
+                return Ok(None);
+            }
 
-        let resp = self.client.post(self.browse_url.clone())
-            .header("user-agent", USER_AGENT)
-            .header("Content-Type", CONTENT_TYPE)
-            .header("SOAPACTION", r#""urn:schemas-nds-com:service:SkyBrowse:2#Browse""#)
-            .body(body)
-            .send()
-            .await?
-            .text()
-            .await?;
+            Ok(Some(((page, total_items), (starting_index + page_len, Some((total_items, sort_criteria))))))
+        })
+    }
 
-        // Parse the response and get element 'Result'
-        let doc = roxmltree::Document::parse(&resp)?;
-        let result_elem = doc.descendants().find(|n|
-            n.tag_name().name() == "Result"
-        ).ok_or("Cannot find `Result` element")?;
-        let inner_xml = result_elem.text()
-            .ok_or("`Result` element is empty")?;
+    /**
+     * Fetch `count` recordings, in as many requests as the box needs to return them all
+     */
+    async fn fetch_page(&self, starting_index: usize, count: usize, sort_criteria: &str) -> Result<Listing> {
+        let mut result = Listing::default();
 
-        // Get the element "/s:Envelope/s:Body/u:BrowseResponse/TotalMatches/text()"
-        let total_matches = doc.descendants().find(|n|
-            n.tag_name().name() == "TotalMatches"
-        ).ok_or("Cannot find `TotalMatches` element")?
-        .text()
-        .ok_or("`TotalMatches` element is empty")?;
+        while result.fetched() < count {
+            let (page, _) = self.fetch_items(starting_index + result.fetched(), count - result.fetched(), sort_criteria).await?;
+            if page.fetched() == 0 {
+                break;
+            }
+            result.extend(page);
+        }
 
-        let total_matches: usize = total_matches.parse()?;
+        Ok(result)
+    }
 
+    async fn fetch_items(&self, starting_index: usize, requested_count: usize, sort_criteria: &str) -> Result<(Listing, usize)> {
+        let (inner_xml, total_matches) = self.browse(RECORDINGS_CONTAINER, "BrowseDirectChildren", starting_index, requested_count, sort_criteria).await?;
 
         // parse inner XML
-        let doc = roxmltree::Document::parse(inner_xml)?;
+        let doc = roxmltree::Document::parse(&inner_xml)?;
+        let mut diagnostics = Vec::new();
         let items: Vec<_> = doc.descendants()
             .filter(|n|n.tag_name().name() == "item")
-            .map(Item::build)
-            .filter_map(Result::ok)
+            .filter_map(|n| Item::build_lenient(n, &mut diagnostics))
             .collect();
 
-        Ok((items, total_matches))
+        Ok((Listing { items, diagnostics }, total_matches))
     }
 
+    /**
+     * The containers and items in a container, e.g. `0` for the root of the content tree, fetched a page at a time
+     */
+    pub async fn browse_children(&self, object_id: &str) -> Result<Vec<ContentObject>> {
+        let mut objects = Vec::new();
 
-    pub async fn remove_items(&self,  matches: &clap::ArgMatches) -> Result<()> {
-        let files: Vec<_> = matches.values_of("filenames")
-            .ok_or("Require at least one item to remove")?
-            .collect();
-
-        for item in files.iter() {
-            self.remove_item(item).await?;
+        loop {
+            let (didl_lite, total_matches) = self.browse(object_id, "BrowseDirectChildren", objects.len(), self.page_size, "").await?;
+            let page = ContentObject::parse_all(&didl_lite)?;
+            if page.is_empty() {
+                break;
+            }
+            objects.extend(page);
+            if objects.len() >= total_matches {
+                break;
+            }
         }
 
-        Ok(())
+        Ok(objects)
     }
 
-    async fn remove_item(&self, item_id: &str) -> Result<()> {
-        eprintln!("Removing: {} using {}", item_id, self.browse_url);
+    /**
+     * One recording, by ID, e.g. `BOOK:688476834`, and any problems parsing its optional fields
+     */
+    pub async fn item(&self, item_id: &str) -> Result<(Item, Vec<Diagnostic>)> {
+        let (didl_lite, _) = self.browse(item_id, "BrowseMetadata", 0, 0, "").await?;
+        let doc = roxmltree::Document::parse(&didl_lite)?;
+        let elem = doc.descendants()
+            .find(|n| n.tag_name().name() == "item")
+            .ok_or_else(|| format!("`{}` is not a recording", item_id))?;
+
+        let mut diagnostics = Vec::new();
+        match Item::build_lenient(elem, &mut diagnostics) {
+            Some(item) => Ok((item, diagnostics)),
+            None => Err(diagnostics.iter()
+                .find(|diagnostic| diagnostic.skipped)
+                .map_or_else(|| format!("Cannot parse `{}`", item_id), |diagnostic| diagnostic.message.clone())
+                .into())
+        }
+    }
 
-        let destroy_elem = format!(
-            r#"<u:DestroyObject xmlns:u="urn:schemas-nds-com:service:SkyBrowse:2">{}</u:DestroyObject>"#,
-            as_elements(&hashmap!{
-                "ObjectID" => item_id
-            }));
+    /**
+     * A container or item itself, rather than its children
+     */
+    pub async fn browse_metadata(&self, object_id: &str) -> Result<ContentObject> {
+        let (didl_lite, _) = self.browse(object_id, "BrowseMetadata", 0, 0, "").await?;
 
-        let body = envelope(destroy_elem.as_str());
+        Ok(ContentObject::parse_all(&didl_lite)?.into_iter().next()
+            .ok_or_else(|| format!("No metadata for `{}`", object_id))?)
+    }
+
+    /**
+     * Call `Browse`, giving the DIDL-Lite result and the total number of matches
+     */
+    async fn browse(&self, object_id: &str, browse_flag: &str, starting_index: usize, requested_count: usize, sort_criteria: &str) -> Result<(String, usize)> {
+        let response = self.client.call(&self.browse_url, &SKY_BROWSE, "Browse", &[
+            ("ObjectID", object_id),
+            ("BrowseFlag", browse_flag),
+            ("Filter", &self.filter),
+            ("StartingIndex", &starting_index.to_string()),
+            ("RequestedCount", &requested_count.to_string()),
+            ("SortCriteria", sort_criteria)
+        ]).await?;
+
+        Ok((response.get("Result")?.to_owned(), response.parse_argument("TotalMatches")?))
+    }
 
-        let resp = self.client.post(self.browse_url.clone())
-            .header("user-agent", USER_AGENT)
-            .header("Content-Type", CONTENT_TYPE)
-            .header("SOAPACTION", r#""urn:schemas-nds-com:service:SkyBrowse:2#DestroyObject""#)
-            .body(body)
-            .send()
-            .await?;
 
-        if resp.status() == 200 {
-            println!("removed: {}", item_id);
-            Ok(())
-        } else {
-            Err("Delete failed".into())
+    pub async fn remove_items<S: AsRef<str>>(&self, item_ids: &[S]) -> Result<()> {
+        for item_id in item_ids {
+            self.remove_item(item_id.as_ref()).await?;
         }
+
+        Ok(())
     }
 
-    pub async fn play(&self,  matches: &clap::ArgMatches) -> Result<()> {
-        let item_res = matches.value_of("filename").expect("Expecting argument");
-
-        let uri = format!("{}?position=0&amp;speed=1", item_res);
-
-        let play_elem = format!(
-            r#"<u:SetAVTransportURI xmlns:u="urn:schemas-nds-com:service:SkyPlay:2">{}</u:SetAVTransportURI>"#,
-            as_elements(&hashmap!{
-                "InstanceID" => "0",
-                "CurrentURI" => &uri,
-                "CurrentURIMetaData" => "NOT_IMPLEMENTED"
-            }));
-        let body = envelope(play_elem.as_str());
-
-        let resp = self.client.post(self.play_url.clone())
-            .header("user-agent", USER_AGENT)
-            .header("Content-Type", CONTENT_TYPE)
-            .header("SOAPACTION", r#""urn:schemas-nds-com:service:SkyPlay:2#SetAVTransportURI""#)
-            .body(body)
-            .send()
-            .await?;
-
-        if resp.status() == 200 {
-            println!("Playing: {}", item_res);
-            Ok(())
-        } else {
-            Err("Play request failed".into())
-        }
+    pub async fn remove_item(&self, item_id: &str) -> Result<()> {
+        self.client.call(&self.browse_url, &SKY_BROWSE, "DestroyObject", &[
+            ("ObjectID", item_id)
+        ]).await?;
+
+        Ok(())
+    }
+
+    /**
+     * Play a recording, given its resource URI, e.g. `file://pvr/290B3177`
+     */
+    pub async fn play(&self, item_res: &str) -> Result<()> {
+        let uri = format!("{}?position=0&speed=1", item_res);
+
+        self.client.call(&self.play_url, &SKY_PLAY, "SetAVTransportURI", &[
+            ("InstanceID", "0"),
+            ("CurrentURI", &uri),
+            ("CurrentURIMetaData", "NOT_IMPLEMENTED")
+        ]).await?;
+
+        Ok(())
     }
 }
 
+/**
+ * Whether the box refused a `Browse` for its `SortCriteria`: UPnP error 709, or 402 from boxes that check it loosely
+ */
+fn rejects_sort(error: &Error) -> bool {
+    matches!(error.kind(), ErrorKind::UPnPError(709, _) | ErrorKind::UPnPError(402, _))
+}
+
 impl fmt::Display for SkyBox {
 
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 
-        let host = self.browse_url.host();
-        //FIXME:
-        write!(f, "{:?}", &host)
+        let host = self.browse_url.host_str().unwrap_or_default();
+        match self.device.as_ref().and_then(|device| device.friendly_name.as_ref()) {
+            Some(friendly_name) => write!(f, "{} at {}", friendly_name, host),
+            None => write!(f, "{}", host)
+        }
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::errors::ErrorKind;
+    use crate::simulator::{Simulator, RECORDINGS, skybox_at, switched_off};
+
+    async fn simulated_box() -> (Simulator, SkyBox) {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let skybox = simulator.skybox();
+        (simulator, skybox)
+    }
+
+    #[test]
+    fn test_display() {
+        let skybox = skybox_at("192.168.2.152");
+        assert_eq!("192.168.2.152", skybox.to_string());
+
+        let skybox = skybox.with_device(DeviceInfo { friendly_name: Some("Lounge".into()), ..DeviceInfo::default() });
+        assert_eq!("Lounge at 192.168.2.152", skybox.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_items() {
+        let (_simulator, skybox) = simulated_box().await;
+
+        let (page, total_matches) = skybox.fetch_items(0, 0, "").await.unwrap();
+        assert_eq!(4, total_matches);
+        assert_eq!(4, page.items.len());
+        assert!(page.diagnostics.is_empty());
+
+        let (page, total_matches) = skybox.fetch_items(1, 2, "").await.unwrap();
+        assert_eq!(4, total_matches);
+        assert_eq!(vec!["BOOK:688476834", "BOOK:688555858"],
+            page.items.iter().map(|item| item.id.as_str()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_list_items() {
+        let (_simulator, skybox) = simulated_box().await;
+
+        let mut progress = Vec::new();
+        let listing = skybox.list_items_with_progress(|fetched, total| progress.push((fetched, total))).await.unwrap();
+
+        assert_eq!(4, listing.items.len());
+        assert_eq!(Some(&(4, 4)), progress.last());
+    }
+
+    #[tokio::test]
+    async fn test_list_items_concurrently() {
+        let (_simulator, skybox) = simulated_box().await;
+        let skybox = skybox.with_page_size(1).with_concurrency(3);
+
+        let mut progress = Vec::new();
+        let listing = skybox.list_items_with_progress(|fetched, total| progress.push((fetched, total))).await.unwrap();
+
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688555858", "BOOK:688614341"],
+            listing.items.iter().map(|item| item.id.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec![(1, 4), (2, 4), (3, 4), (4, 4)], progress);
+    }
+
+    #[tokio::test]
+    async fn test_items() {
+        let (_simulator, skybox) = simulated_box().await;
+        let skybox = skybox.with_page_size(3);
+
+        let ids: Vec<String> = skybox.items().map_ok(|item| item.id).try_collect().await.unwrap();
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688555858", "BOOK:688614341"], ids);
+
+        // Stopping early
+        let first: Vec<Item> = skybox.items().take(1).try_collect().await.unwrap();
+        assert_eq!("BOOK:687878212", first[0].id);
+
+        let totals: Vec<(usize, usize)> = skybox.pages().map_ok(|(page, total)| (page.fetched(), total)).try_collect().await.unwrap();
+        assert_eq!(vec![(3, 4), (1, 4)], totals);
+    }
+
+    #[tokio::test]
+    async fn test_properties() {
+        let (_simulator, skybox) = simulated_box().await;
+
+        let listing = skybox.with_properties(Item::PROPERTIES).list_items().await.unwrap();
+        assert_eq!(4, listing.items.len());
+        assert!(listing.diagnostics.is_empty());
+
+        let (_simulator, skybox) = simulated_box().await;
+        let skybox = skybox.with_properties(&["res", "dc:title", "upnp:channelName", "upnp:recordedStartDateTime", "upnp:recordedDuration"]);
+        let listing = skybox.list_items().await.unwrap();
+        assert_eq!(4, listing.items.len());
+        assert!(listing.items.iter().all(|item| item.description.is_none() && item.flags.is_none()));
+        assert!(listing.diagnostics.iter().all(|diagnostic| !diagnostic.skipped));
+    }
+
+    #[tokio::test]
+    async fn test_sorted_by_box() {
+        let (_simulator, skybox) = simulated_box().await;
+        let skybox = skybox.with_page_size(3).with_sort("-time".parse().unwrap());
+
+        let ids = |listing: &Listing| listing.items.iter().map(|item| item.id.clone()).collect::<Vec<_>>();
+        let listing = skybox.list_items().await.unwrap();
+        assert_eq!(vec!["BOOK:688614341", "BOOK:688555858", "BOOK:688476834", "BOOK:687878212"], ids(&listing));
+
+        // Paged by the box
+        let pages: Vec<(Listing, usize)> = skybox.pages().try_collect().await.unwrap();
+        assert_eq!(vec![vec!["BOOK:688614341", "BOOK:688555858", "BOOK:688476834"], vec!["BOOK:687878212"]],
+            pages.iter().map(|(page, _)| ids(page)).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_sort_rejected_by_box() {
+        let (_simulator, skybox) = simulated_box().await;
+        let skybox = skybox.with_page_size(3).with_sort("-channel".parse().unwrap());
+
+        let ids = |listing: &Listing| listing.items.iter().map(|item| item.id.clone()).collect::<Vec<_>>();
+        let listing = skybox.list_items().await.unwrap();
+        assert_eq!(vec!["BOOK:688555858", "BOOK:687878212", "BOOK:688476834", "BOOK:688614341"], ids(&listing));
+
+        // Sorted here, in a single page
+        let pages: Vec<(Listing, usize)> = skybox.pages().try_collect().await.unwrap();
+        assert_eq!(vec![(ids(&listing), 4)], pages.iter().map(|(page, total)| (ids(page), *total)).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_browse_children() {
+        let (_simulator, skybox) = simulated_box().await;
+
+        let root = skybox.browse_children("0").await.unwrap();
+        assert_eq!(vec!["3"], root.iter().map(|object| object.id.as_str()).collect::<Vec<_>>());
+        assert!(root[0].container);
+        assert_eq!(Some(4), root[0].child_count);
+
+        let skybox = skybox.with_page_size(3);
+        let recordings = skybox.browse_children(RECORDINGS_CONTAINER).await.unwrap();
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688555858", "BOOK:688614341"],
+            recordings.iter().map(|object| object.id.as_str()).collect::<Vec<_>>());
+        assert!(recordings.iter().all(|object| !object.container));
+
+        match skybox.browse_children("BOOK:687878212").await.unwrap_err().kind() {
+            ErrorKind::UPnPError(710, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_browse_metadata() {
+        let (_simulator, skybox) = simulated_box().await;
+
+        let planner = skybox.browse_metadata(RECORDINGS_CONTAINER).await.unwrap();
+        assert_eq!(Some("Planner"), planner.title.as_deref());
+        assert_eq!(Some("0"), planner.parent_id.as_deref());
+
+        let recording = skybox.browse_metadata("BOOK:688555858").await.unwrap();
+        assert_eq!(Some("Match of the Day"), recording.title.as_deref());
+        assert_eq!(Some("3"), recording.parent_id.as_deref());
+
+        match skybox.browse_metadata("BOOK:1").await.unwrap_err().kind() {
+            ErrorKind::NoSuchObject(701, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_item() {
+        let (_simulator, skybox) = simulated_box().await;
+
+        let (item, diagnostics) = skybox.item("BOOK:688476834").await.unwrap();
+        assert_eq!("BOOK:688476834", item.id);
+        assert!(item.resource.is_some());
+        assert!(diagnostics.is_empty());
+
+        match skybox.item("BOOK:1").await.unwrap_err().kind() {
+            ErrorKind::NoSuchObject(701, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+        assert_eq!("`3` is not a recording", skybox.item(RECORDINGS_CONTAINER).await.unwrap_err().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_list_items_reports_skipped() {
+        let recordings = RECORDINGS.replacen("<dc:title>Match of the Day</dc:title>", "", 1);
+        let simulator = Simulator::start(&recordings).await.unwrap();
+        let skybox = simulator.skybox();
+
+        let mut progress = Vec::new();
+        let listing = skybox.list_items_with_progress(|fetched, total| progress.push((fetched, total))).await.unwrap();
+
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688614341"],
+            listing.items.iter().map(|item| item.id.as_str()).collect::<Vec<_>>());
+        assert_eq!(1, listing.diagnostics.len());
+        assert_eq!("skipped BOOK:688555858: Element `title` is absent", listing.diagnostics[0].to_string());
+        assert_eq!(Some(&(4, 4)), progress.last());
+    }
+
+    #[tokio::test]
+    async fn test_connection_failure() {
+        let (_simulator, skybox) = simulated_box().await;
+        assert!(!skybox.remove_item("BOOK:1").await.unwrap_err().is_connection_failure());
+
+        assert!(switched_off().remove_item("BOOK:1").await.unwrap_err().is_connection_failure());
+    }
+
+    #[tokio::test]
+    async fn test_with_client() {
+        let (_simulator, skybox) = simulated_box().await;
+        let actions = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let skybox = skybox.with_client(soap::Client::new().with_logger({
+            let actions = actions.clone();
+            move |line| actions.lock().unwrap().push(line.split(' ').next().unwrap_or_default().to_owned())
+        }));
+
+        skybox.play("file://pvr/290B3177").await.unwrap();
+        assert_eq!(vec!["SetAVTransportURI", "SetAVTransportURI"], *actions.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_remove_items() {
+        let (simulator, skybox) = simulated_box().await;
+
+        skybox.remove_items(&["BOOK:687878212", "BOOK:688614341"]).await.unwrap();
+        assert_eq!(vec!["BOOK:688476834", "BOOK:688555858"], simulator.recording_ids());
+    }
+
+    #[tokio::test]
+    async fn test_remove_item() {
+        let (simulator, skybox) = simulated_box().await;
+
+        skybox.remove_item("BOOK:688476834").await.unwrap();
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688555858", "BOOK:688614341"], simulator.recording_ids());
+
+        match skybox.remove_item("BOOK:688476834").await.unwrap_err().kind() {
+            ErrorKind::NoSuchObject(701, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_play() {
+        let (simulator, skybox) = simulated_box().await;
+
+        skybox.play("file://pvr/290B3177").await.unwrap();
+
+        assert_eq!(Some("file://pvr/290B3177?position=0&speed=1".into()), simulator.now_playing());
+
+        match skybox.play("file://pvr/DEADBEEF").await.unwrap_err().kind() {
+            ErrorKind::NoSuchObject(716, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+    }
+}
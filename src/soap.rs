@@ -0,0 +1,554 @@
+use super::common::{SKY_BROWSE, SKY_PLAY};
+use super::common::errors::*;
+
+use ssdp_client::URN;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use url::Url;
+
+const USER_AGENT: &str = "SKY_skyplus";
+const CONTENT_TYPE: &str = r#"text/xml; charset="utf-8""#;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/**
+ * Escape text for use in XML character data or attribute values
+ */
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c)
+        }
+    }
+    result
+}
+
+pub(crate) fn envelope(body: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="utf-8"?>
+        <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+            <s:Body>{}</s:Body>
+        </s:Envelope>"#, body)
+}
+
+/**
+ * The content of an argument element: escaped text, or nested elements
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Elements(Vec<Element>),
+}
+
+/**
+ * An argument element, optionally in its own namespace
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub namespace: Option<(String, String)>, // (Prefix, URI)
+    pub value: Value,
+}
+
+impl Element {
+
+    pub fn text(name: &str, text: &str) -> Self {
+        Element { name: name.into(), namespace: None, value: Value::Text(text.into()) }
+    }
+
+    pub fn nested(name: &str, children: Vec<Element>) -> Self {
+        Element { name: name.into(), namespace: None, value: Value::Elements(children) }
+    }
+
+    /**
+     * Qualify this element with `prefix`, declaring the prefix as `uri`
+     */
+    pub fn with_namespace(mut self, prefix: &str, uri: &str) -> Self {
+        self.namespace = Some((prefix.into(), uri.into()));
+        self
+    }
+
+    fn write(&self, out: &mut String) {
+        let name = match &self.namespace {
+            Some((prefix, _)) => format!("{}:{}", prefix, self.name),
+            None => self.name.clone()
+        };
+
+        out.push('<');
+        out.push_str(&name);
+        if let Some((prefix, uri)) = &self.namespace {
+            out.push_str(&format!(r#" xmlns:{}="{}""#, prefix, escape(uri)));
+        }
+        out.push('>');
+
+        match &self.value {
+            Value::Text(text) => out.push_str(&escape(text)),
+            Value::Elements(children) => children.iter().for_each(|child| child.write(out))
+        }
+
+        out.push_str("</");
+        out.push_str(&name);
+        out.push('>');
+    }
+}
+
+/**
+ * A SOAP action request. Arguments are written in the order they are added.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub service_type: String,
+    pub action: String,
+    pub arguments: Vec<Element>,
+}
+
+impl Request {
+
+    pub fn new(service_type: &URN, action: &str) -> Self {
+        Request { service_type: service_type.to_string(), action: action.into(), arguments: Vec::new() }
+    }
+
+    /**
+     * Add a text argument
+     */
+    pub fn argument(self, name: &str, value: &str) -> Self {
+        self.element(Element::text(name, value))
+    }
+
+    /**
+     * Add a nested or namespaced argument
+     */
+    pub fn element(mut self, element: Element) -> Self {
+        self.arguments.push(element);
+        self
+    }
+
+    /**
+     * The value of the `SOAPACTION` header
+     */
+    pub fn soap_action(&self) -> String {
+        format!(r#""{}#{}""#, self.service_type, self.action)
+    }
+
+    /**
+     * The envelope to POST to the service's control URL
+     */
+    pub fn envelope(&self) -> String {
+        let mut body = format!(r#"<u:{} xmlns:u="{}">"#, self.action, escape(&self.service_type));
+        for argument in self.arguments.iter() {
+            argument.write(&mut body);
+        }
+        body.push_str(&format!("</u:{}>", self.action));
+
+        envelope(&body)
+    }
+}
+
+/**
+ * The content of an `s:Fault` element
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Fault {
+    pub fault_code: String,
+    pub fault_string: String,
+    pub upnp_error: Option<(u32, String)>, // (errorCode, errorDescription)
+}
+
+impl Fault {
+
+    /**
+     * Find the fault in a response envelope, if any
+     */
+    pub fn parse(body: &str) -> Option<Fault> {
+        let doc = roxmltree::Document::parse(body).ok()?;
+        let fault_elem = doc.descendants().find(|n| n.tag_name().name() == "Fault")?;
+
+        let text_of = |node: roxmltree::Node, name: &str| node.descendants()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .map(|text| text.trim().to_owned());
+
+        let upnp_error = fault_elem.descendants()
+            .find(|n| n.tag_name().name() == "UPnPError")
+            .and_then(|elem| {
+                let code = text_of(elem, "errorCode")?.parse().ok()?;
+                Some((code, text_of(elem, "errorDescription").unwrap_or_default()))
+            });
+
+        Some(Fault {
+            fault_code: text_of(fault_elem, "faultcode").unwrap_or_default(),
+            fault_string: text_of(fault_elem, "faultstring").unwrap_or_default(),
+            upnp_error
+        })
+    }
+
+    /**
+     * Classify the fault by its UPnP error code, which depends upon the service raising it
+     */
+    pub fn into_error_kind(self, service_type: &str) -> ErrorKind {
+        let (code, description) = match self.upnp_error {
+            Some(upnp_error) => upnp_error,
+            None => return ErrorKind::SoapFault(self.fault_code, self.fault_string)
+        };
+
+        let (browse, play) = (SKY_BROWSE.to_string(), SKY_PLAY.to_string());
+        match code {
+            701 if service_type == browse => ErrorKind::NoSuchObject(code, description),
+            716 if service_type == play => ErrorKind::NoSuchObject(code, description),
+            701 | 715 if service_type == play => ErrorKind::Busy(code, description),
+            _ => ErrorKind::UPnPError(code, description)
+        }
+    }
+}
+
+impl Request {
+
+    /**
+     * The error for an unsuccessful response to this request
+     */
+    pub fn error_for_response(&self, status: u16, body: &str) -> Error {
+        match Fault::parse(body) {
+            Some(fault) => fault.into_error_kind(&self.service_type).into(),
+            None => ErrorKind::HttpStatus(status).into()
+        }
+    }
+}
+
+/**
+ * The output arguments of a SOAP action
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Response {
+    pub arguments: HashMap<String, String>,
+}
+
+impl Response {
+
+    /**
+     * Parse the arguments of the action response element in an envelope
+     */
+    pub fn parse(body: &str) -> Result<Response> {
+        let doc = roxmltree::Document::parse(body)?;
+        let body_elem = doc.descendants()
+            .find(|n| n.tag_name().name() == "Body")
+            .ok_or("Cannot find `Body` element")?;
+        let response_elem = body_elem.first_element_child()
+            .ok_or("Cannot find action response element")?;
+
+        let arguments = response_elem.children()
+            .filter(|n| n.is_element())
+            .map(|n| (n.tag_name().name().into(), n.text().unwrap_or_default().into()))
+            .collect();
+
+        Ok(Response { arguments })
+    }
+
+    pub fn get(&self, name: &str) -> Result<&str> {
+        self.arguments.get(name)
+            .map(String::as_str)
+            .ok_or_else(|| format!("Cannot find `{}` element", name).into())
+    }
+
+    pub fn parse_argument<T>(&self, name: &str) -> Result<T>
+        where T: FromStr, Error: From<T::Err>
+    {
+        Ok(self.get(name)?.parse()?)
+    }
+}
+
+type Logger = Arc<dyn Fn(&str) + Send + Sync>;
+
+/**
+ * Invokes SOAP actions on a UPnP service's control URL
+ */
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    timeout: Duration,
+    retries: usize,
+    logger: Option<Logger>,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("logger", &self.logger.is_some())
+            .finish()
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+impl Client {
+
+    pub fn new() -> Self {
+        Client { http: reqwest::Client::new(), timeout: DEFAULT_TIMEOUT, retries: 1, logger: None }
+    }
+
+    /**
+     * Fail each attempt after `timeout`
+     */
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /**
+     * Retry up to `retries` times when the connection cannot be made, as the action cannot have been received
+     */
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /**
+     * Pass a line describing each attempt and its outcome to `logger`, e.g. `Browse http://...: HTTP 200 in 12ms`
+     */
+    pub fn with_logger<F>(mut self, logger: F) -> Self
+        where F: Fn(&str) + Send + Sync + 'static
+    {
+        self.logger = Some(Arc::new(logger));
+        self
+    }
+
+    fn log<F: FnOnce() -> String>(&self, message: F) {
+        if let Some(logger) = &self.logger {
+            logger(&message());
+        }
+    }
+
+    /**
+     * Invoke `action` with text arguments, in order
+     */
+    pub async fn call(&self, control_url: &Url, service_type: &URN, action: &str, arguments: &[(&str, &str)]) -> Result<Response> {
+        let request = arguments.iter()
+            .fold(Request::new(service_type, action), |request, (name, value)| request.argument(name, value));
+
+        self.send(control_url, &request).await
+    }
+
+    /**
+     * Invoke a request, which may have nested arguments
+     */
+    pub async fn send(&self, control_url: &Url, request: &Request) -> Result<Response> {
+        let mut attempt = 0;
+        let start = Instant::now();
+        let resp = loop {
+            self.log(|| format!("{} {}", request.action, control_url));
+            let result = self.http.post(control_url.clone())
+                .header("user-agent", USER_AGENT)
+                .header("Content-Type", CONTENT_TYPE)
+                .header("SOAPACTION", request.soap_action())
+                .timeout(self.timeout)
+                .body(request.envelope())
+                .send()
+                .await;
+
+            match result {
+                Err(error) if error.is_connect() && attempt < self.retries => {
+                    attempt += 1;
+                    self.log(|| format!("{} {}: {}, retry {} of {}", request.action, control_url, error, attempt, self.retries));
+                }
+                Err(error) => {
+                    self.log(|| format!("{} {}: {}", request.action, control_url, error));
+                    return Err(error.into());
+                }
+                Ok(resp) => break resp
+            }
+        };
+
+        let status = resp.status();
+        self.log(|| format!("{} {}: HTTP {} in {}ms", request.action, control_url, status.as_u16(), start.elapsed().as_millis()));
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(request.error_for_response(status.as_u16(), &body));
+        }
+
+        Response::parse(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_envelope() {
+        let xml_source = envelope("<test-element/>");
+        let document = roxmltree::Document::parse(xml_source.as_str()).unwrap();
+
+        let body_element = document.root_element().first_element_child().unwrap();
+        let payload_element = body_element.first_element_child().unwrap();
+
+        assert_eq!("Body", body_element.tag_name().name());
+        assert_eq!("test-element", payload_element.tag_name().name());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("file://pvr/290B3177?position=0&amp;speed=1", escape("file://pvr/290B3177?position=0&speed=1"));
+        assert_eq!("&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;", escape(r#"<a href="x">Tom & Jerry's</a>"#));
+    }
+
+    #[test]
+    fn test_argument_order() {
+        let request = Request::new(&SKY_BROWSE, "Browse")
+            .argument("ObjectID", "3")
+            .argument("BrowseFlag", "BrowseDirectChildren")
+            .argument("Filter", "*")
+            .argument("SortCriteria", "");
+
+        assert!(request.envelope().contains(concat!(
+            r#"<u:Browse xmlns:u="urn:schemas-nds-com:service:SkyBrowse:2">"#,
+            "<ObjectID>3</ObjectID><BrowseFlag>BrowseDirectChildren</BrowseFlag><Filter>*</Filter><SortCriteria></SortCriteria>",
+            "</u:Browse>")));
+        assert_eq!(r#""urn:schemas-nds-com:service:SkyBrowse:2#Browse""#, request.soap_action());
+    }
+
+    #[test]
+    fn test_escaped_arguments_round_trip() {
+        let id = r#"BOOK:1&2<3>"4'"#;
+        let request = Request::new(&SKY_BROWSE, "DestroyObject").argument("ObjectID", id);
+
+        let xml_source = request.envelope();
+        let document = roxmltree::Document::parse(&xml_source).unwrap();
+        let argument = document.descendants().find(|n| n.tag_name().name() == "ObjectID").unwrap();
+
+        assert_eq!(Some(id), argument.text());
+    }
+
+    fn fault_body(code: u32, description: &str) -> String {
+        envelope(&format!(
+            r#"<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError></detail></s:Fault>"#,
+            code, description))
+    }
+
+    #[test]
+    fn test_parse_fault() {
+        assert_eq!(Some(Fault {
+            fault_code: "s:Client".into(),
+            fault_string: "UPnPError".into(),
+            upnp_error: Some((701, "No such object".into()))
+        }), Fault::parse(&fault_body(701, "No such object")));
+
+        assert_eq!(None, Fault::parse(&envelope("<u:BrowseResponse/>")));
+        assert_eq!(None, Fault::parse("Internal Server Error"));
+    }
+
+    #[test]
+    fn test_error_for_response() {
+        use crate::common::SKY_PLAY;
+
+        let browse = Request::new(&SKY_BROWSE, "DestroyObject");
+        let play = Request::new(&SKY_PLAY, "SetAVTransportURI");
+
+        match browse.error_for_response(500, &fault_body(701, "No such object")).kind() {
+            ErrorKind::NoSuchObject(701, description) => assert_eq!("No such object", description),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+        match play.error_for_response(500, &fault_body(715, "Content BUSY")).kind() {
+            ErrorKind::Busy(715, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+        match play.error_for_response(500, &fault_body(402, "Invalid Args")).kind() {
+            ErrorKind::UPnPError(402, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+        match play.error_for_response(503, "").kind() {
+            ErrorKind::HttpStatus(503) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = envelope(r#"<u:BrowseResponse xmlns:u="urn:schemas-nds-com:service:SkyBrowse:2"><Result>&lt;DIDL-Lite/&gt;</Result><NumberReturned>0</NumberReturned><TotalMatches>42</TotalMatches><UpdateID></UpdateID></u:BrowseResponse>"#);
+        let response = Response::parse(&body).unwrap();
+
+        assert_eq!("<DIDL-Lite/>", response.get("Result").unwrap());
+        assert_eq!("", response.get("UpdateID").unwrap());
+        assert_eq!(42, response.parse_argument::<usize>("TotalMatches").unwrap());
+        assert!(response.get("Absent").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_call() {
+        use crate::simulator::{Simulator, RECORDINGS};
+
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = Client::new().with_logger({
+            let log = log.clone();
+            move |line| log.lock().unwrap().push(line.to_owned())
+        });
+
+        let response = client.call(&simulator.browse_url(), &SKY_BROWSE, "Browse", &[
+            ("ObjectID", "3"),
+            ("BrowseFlag", "BrowseDirectChildren"),
+            ("Filter", "*"),
+            ("StartingIndex", "0"),
+            ("RequestedCount", "1"),
+            ("SortCriteria", ""),
+        ]).await.unwrap();
+        assert_eq!(1, response.parse_argument::<usize>("NumberReturned").unwrap());
+        assert_eq!(4, response.parse_argument::<usize>("TotalMatches").unwrap());
+
+        let error = client.call(&simulator.browse_url(), &SKY_BROWSE, "DestroyObject", &[("ObjectID", "BOOK:0")]).await.unwrap_err();
+        match error.kind() {
+            ErrorKind::NoSuchObject(701, _) => (),
+            kind => panic!("Unexpected {:?}", kind)
+        }
+
+        let log = log.lock().unwrap();
+        assert_eq!(4, log.len());
+        assert_eq!(format!("Browse {}", simulator.browse_url()), log[0]);
+        assert!(log[1].starts_with(&format!("Browse {}: HTTP 200 in ", simulator.browse_url())));
+        assert!(log[3].starts_with(&format!("DestroyObject {}: HTTP 500 in ", simulator.browse_url())));
+    }
+
+    #[tokio::test]
+    async fn test_client_retries() {
+        let url = Url::parse("http://127.0.0.1:9/SkyBrowse2").unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = Client::new().with_retries(2).with_timeout(Duration::from_secs(2)).with_logger({
+            let log = log.clone();
+            move |line| log.lock().unwrap().push(line.to_owned())
+        });
+
+        assert!(client.call(&url, &SKY_BROWSE, "Browse", &[]).await.unwrap_err().is_connection_failure());
+        assert_eq!(6, log.lock().unwrap().len()); // Three attempts, each logged before and after
+    }
+
+    #[test]
+    fn test_nested_namespaced_arguments() {
+        let request = Request::new(&SKY_BROWSE, "Search")
+            .element(Element::nested("Query", vec![
+                Element::text("Title", "Tom & Jerry"),
+                Element::text("Channel", "102"),
+            ]).with_namespace("q", "urn:example:query"));
+
+        let xml_source = request.envelope();
+        let document = roxmltree::Document::parse(&xml_source).unwrap();
+        let query = document.descendants().find(|n| n.tag_name().name() == "Query").unwrap();
+
+        assert_eq!(Some("urn:example:query"), query.tag_name().namespace());
+        let children: Vec<_> = query.children()
+            .map(|n| (n.tag_name().name(), n.text().unwrap()))
+            .collect();
+        assert_eq!(vec![("Title", "Tom & Jerry"), ("Channel", "102")], children);
+    }
+}
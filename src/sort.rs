@@ -0,0 +1,249 @@
+use super::common::errors::*;
+use super::item::Item;
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/**
+ * The fields of `Item` that recordings can be sorted by
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    Id,
+    Res,
+    Title,
+    Description,
+    Viewed,
+    RecordedStartTime,
+    RecordedDuration,
+    ChannelName,
+    ChannelNumber,
+    SeriesId,
+    ServiceType,
+    ScheduledStartTime,
+    Size,
+    LastViewedTime,
+}
+
+impl SortField {
+
+    /**
+     * The UPnP property a box can sort by in the same order, if any
+     */
+    fn property(self) -> Option<&'static str> {
+        match self {
+            SortField::Title => Some("dc:title"),
+            SortField::Description => Some("dc:description"),
+            SortField::RecordedStartTime => Some("upnp:recordedStartDateTime"),
+            SortField::RecordedDuration => Some("upnp:recordedDuration"),
+            SortField::ChannelName => Some("upnp:channelName"),
+            SortField::SeriesId => Some("upnp:seriesID"),
+            SortField::ScheduledStartTime => Some("upnp:scheduledStartTime"),
+            _ => None
+        }
+    }
+
+    fn compare(self, a: &Item, b: &Item) -> Ordering {
+        match self {
+            SortField::Id => a.id.cmp(&b.id),
+            SortField::Res => a.res.cmp(&b.res),
+            SortField::Title => a.title.cmp(&b.title),
+            SortField::Description => a.description.cmp(&b.description),
+            SortField::Viewed => a.viewed.cmp(&b.viewed),
+            SortField::RecordedStartTime => a.recorded_starttime.cmp(&b.recorded_starttime),
+            SortField::RecordedDuration => a.recorded_duration.cmp(&b.recorded_duration),
+            SortField::ChannelName => a.channel_name.cmp(&b.channel_name),
+            SortField::ChannelNumber => a.channel_number.cmp(&b.channel_number),
+            SortField::SeriesId => a.series_id.cmp(&b.series_id),
+            SortField::ServiceType => (a.service_type as u8).cmp(&(b.service_type as u8)),
+            SortField::ScheduledStartTime => a.scheduled_starttime.cmp(&b.scheduled_starttime),
+            SortField::Size => a.resource.as_ref().map(|r| r.size).cmp(&b.resource.as_ref().map(|r| r.size)),
+            SortField::LastViewedTime => a.last_viewed_time.cmp(&b.last_viewed_time),
+        }
+    }
+}
+
+impl FromStr for SortField {
+    type Err = Error;
+
+    /**
+     * Parse a field name, ignoring case and underscores, either as serialised, e.g. `RecordedStartTime`,
+     * or by a shorter alias, e.g. `time`
+     */
+    fn from_str(name: &str) -> Result<SortField> {
+        let field = match name.to_ascii_lowercase().replace('_', "").as_str() {
+            "id" => SortField::Id,
+            "res" => SortField::Res,
+            "title" => SortField::Title,
+            "description" => SortField::Description,
+            "viewed" => SortField::Viewed,
+            "recordedstarttime" | "time" | "start" => SortField::RecordedStartTime,
+            "recordedduration" | "duration" => SortField::RecordedDuration,
+            "channelname" | "channel" => SortField::ChannelName,
+            "channelnumber" | "channelnr" => SortField::ChannelNumber,
+            "seriesid" | "series" => SortField::SeriesId,
+            "servicetype" | "genre" => SortField::ServiceType,
+            "scheduledstarttime" | "scheduled" => SortField::ScheduledStartTime,
+            "size" => SortField::Size,
+            "lastviewedtime" | "lastviewed" => SortField::LastViewedTime,
+            _ => return Err(format!("Cannot sort by unknown field: {}", name).into())
+        };
+        Ok(field)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/**
+ * An ordering of recordings by one or more fields, the first field being the most significant
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sort {
+    pub keys: Vec<SortKey>,
+}
+
+impl Sort {
+
+    pub fn new(keys: Vec<SortKey>) -> Self {
+        Sort { keys }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /**
+     * The same fields, each in the opposite direction
+     */
+    pub fn reversed(self) -> Self {
+        Sort::new(self.keys.into_iter()
+            .map(|key| SortKey { descending: !key.descending, ..key })
+            .collect())
+    }
+
+    /**
+     * The `SortCriteria` argument of `Browse`, e.g. `+dc:title,-upnp:recordedStartDateTime`,
+     * or `None` if any field has no UPnP property to sort by
+     */
+    pub fn sort_criteria(&self) -> Option<String> {
+        let criteria = self.keys.iter()
+            .map(|key| key.field.property().map(|property| format!("{}{}", if key.descending { '-' } else { '+' }, property)))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(criteria.join(","))
+    }
+
+    pub fn compare(&self, a: &Item, b: &Item) -> Ordering {
+        self.keys.iter()
+            .map(|key| {
+                let ordering = key.field.compare(a, b);
+                if key.descending { ordering.reverse() } else { ordering }
+            })
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /**
+     * Sort items in place. The sort is stable: items equal under every key keep their order.
+     */
+    pub fn apply(&self, items: &mut [Item]) {
+        if !self.is_empty() {
+            items.sort_by(|a, b| self.compare(a, b));
+        }
+    }
+}
+
+impl FromStr for Sort {
+    type Err = Error;
+
+    /**
+     * Parse a comma separated list of fields, each descending if prefixed by `-`, e.g. `title,channel,-duration`
+     */
+    fn from_str(spec: &str) -> Result<Sort> {
+        let keys = spec.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| match name.strip_prefix('-') {
+                Some(name) => Ok(SortKey { field: name.parse()?, descending: true }),
+                None => Ok(SortKey { field: name.trim_start_matches('+').parse()?, descending: false })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Sort::new(keys))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::items;
+
+    fn sorted_ids(spec: &str) -> Vec<String> {
+        let mut items = items();
+        spec.parse::<Sort>().unwrap().apply(&mut items);
+        items.into_iter().map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let sort: Sort = "title, Channel,-duration".parse().unwrap();
+        assert_eq!(vec![
+            SortKey { field: SortField::Title, descending: false },
+            SortKey { field: SortField::ChannelName, descending: false },
+            SortKey { field: SortField::RecordedDuration, descending: true },
+        ], sort.keys);
+
+        assert!("title,colour".parse::<Sort>().is_err());
+        assert!("".parse::<Sort>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_single_key() {
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688555858", "BOOK:688614341"], sorted_ids("time"));
+        assert_eq!(vec!["BOOK:688614341", "BOOK:688555858", "BOOK:688476834", "BOOK:687878212"], sorted_ids("-RecordedStartTime"));
+    }
+
+    #[test]
+    fn test_multiple_keys() {
+        // Both Ewan McGregor recordings share a title, so duration breaks the tie
+        assert_eq!(vec!["BOOK:688476834", "BOOK:687878212", "BOOK:688555858", "BOOK:688614341"], sorted_ids("title,duration"));
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688555858", "BOOK:688614341"], sorted_ids("title,-duration"));
+    }
+
+    #[test]
+    fn test_nested_field() {
+        assert_eq!(vec!["BOOK:688555858", "BOOK:687878212", "BOOK:688476834", "BOOK:688614341"], sorted_ids("-size"));
+    }
+
+    #[test]
+    fn test_genre() {
+        // By code: News 5, Sport 7, Documentary 11
+        assert_eq!(vec!["BOOK:688614341", "BOOK:688555858", "BOOK:687878212", "BOOK:688476834"], sorted_ids("genre"));
+    }
+
+    #[test]
+    fn test_stable() {
+        // Equal channels keep their fetched order
+        assert_eq!(vec!["BOOK:687878212", "BOOK:688476834", "BOOK:688614341", "BOOK:688555858"], sorted_ids("channel"));
+    }
+
+    #[test]
+    fn test_sort_criteria() {
+        assert_eq!(Some("+dc:title,-upnp:recordedStartDateTime".to_string()), "title,-time".parse::<Sort>().unwrap().sort_criteria());
+        assert_eq!(Some(String::new()), Sort::default().sort_criteria());
+        assert_eq!(None, "title,-size".parse::<Sort>().unwrap().sort_criteria());
+    }
+
+    #[test]
+    fn test_reversed() {
+        let sort = "genre,-time".parse::<Sort>().unwrap().reversed();
+        assert_eq!(vec![
+            SortKey { field: SortField::ServiceType, descending: true },
+            SortKey { field: SortField::RecordedStartTime, descending: false },
+        ], sort.keys);
+    }
+}
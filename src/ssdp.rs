@@ -0,0 +1,186 @@
+use super::common::errors::Result;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, timeout_at};
+
+pub const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+pub const SSDP_PORT: u16 = 1900;
+
+// Between repeated M-SEARCH requests
+const REPEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+/**
+ * How to search for devices
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchOptions {
+    pub timeout: Duration,
+    pub mx: u8,
+    pub repeats: usize,
+    pub interface: Ipv4Addr,
+    pub target: SocketAddr,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            timeout: Duration::from_secs(5),
+            mx: 2,
+            repeats: 1,
+            interface: Ipv4Addr::UNSPECIFIED,
+            target: (SSDP_ADDR, SSDP_PORT).into(),
+        }
+    }
+}
+
+/**
+ * An SSDP datagram: an HTTP-like start line and headers, without a body
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub start_line: String,
+    headers: HashMap<String, String>,
+}
+
+impl Message {
+
+    pub fn parse(datagram: &str) -> Option<Message> {
+        let mut lines = datagram.lines();
+        let start_line = lines.next()?.trim().to_owned();
+
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_uppercase(), value.trim().to_owned()))
+            .collect();
+
+        Some(Message{start_line, headers})
+    }
+
+    /**
+     * The value of a header, whatever the case of its name
+     */
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_uppercase()).map(String::as_str)
+    }
+
+    /**
+     * The UDN part of the `USN` header, e.g. `uuid:...` from `uuid:...::urn:...`
+     */
+    pub fn udn(&self) -> Option<&str> {
+        self.header("USN").map(|usn| usn.split("::").next().unwrap_or(usn))
+    }
+
+    /**
+     * The `max-age` directive of the `CACHE-CONTROL` header, in seconds
+     */
+    pub fn max_age(&self) -> Option<u64> {
+        self.header("CACHE-CONTROL")?
+            .split(',')
+            .filter_map(|directive| directive.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("max-age"))
+            .and_then(|(_, value)| value.trim().parse().ok())
+    }
+}
+
+/**
+ * Send an M-SEARCH request for each search target, and collect the responses until the timeout.
+ * Responses repeating an earlier `ST` and `LOCATION` are dropped.
+ */
+pub async fn search(search_targets: &[String], options: &SearchOptions) -> Result<Vec<Message>> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    if !options.interface.is_unspecified() {
+        socket.set_multicast_if_v4(&options.interface)?;
+    }
+    socket.bind(&SocketAddr::from((options.interface, 0)).into())?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+
+    let deadline = Instant::now() + options.timeout;
+    for repeat in 0..options.repeats.max(1) {
+        if repeat > 0 {
+            tokio::time::sleep(REPEAT_INTERVAL).await;
+        }
+        for search_target in search_targets {
+            socket.send_to(m_search(search_target, options).as_bytes(), options.target).await?;
+        }
+    }
+
+    let mut responses: Vec<Message> = Vec::new();
+    let mut buffer = [0u8; 2048];
+    while let Ok(received) = timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        let (len, _) = received?;
+        let response = match Message::parse(&String::from_utf8_lossy(&buffer[..len])) {
+            Some(response) if response.start_line.starts_with("HTTP/1.1 200") => response,
+            _ => continue
+        };
+
+        let seen = responses.iter().any(|other|
+            other.header("ST") == response.header("ST") && other.header("LOCATION") == response.header("LOCATION"));
+        if !seen && search_targets.iter().any(|target| response.header("ST") == Some(target.as_str())) {
+            responses.push(response);
+        }
+    }
+
+    Ok(responses)
+}
+
+fn m_search(search_target: &str, options: &SearchOptions) -> String {
+    format!("M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+        options.target, options.mx, search_target)
+}
+
+/**
+ * Bind a socket to the SSDP port, sharing it with other listeners, and join the SSDP multicast group on `interface`
+ */
+pub fn bind_multicast(interface: Ipv4Addr) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    socket.join_multicast_v4(&SSDP_ADDR, &interface)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{SKY_BROWSE, SKY_PLAY};
+    use crate::simulator::{Simulator, RECORDINGS};
+
+    #[test]
+    fn test_parse() {
+        let message = Message::parse("NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCache-Control: max-age=1800\r\nLOCATION: http://192.168.2.152:49153/description0.xml\r\nnts: ssdp:alive\r\nUSN: uuid:444D5376::urn:schemas-nds-com:service:SkyBrowse:2\r\n\r\n").unwrap();
+
+        assert_eq!("NOTIFY * HTTP/1.1", message.start_line);
+        assert_eq!(Some("http://192.168.2.152:49153/description0.xml"), message.header("location"));
+        assert_eq!(Some("ssdp:alive"), message.header("NTS"));
+        assert_eq!(Some("uuid:444D5376"), message.udn());
+        assert_eq!(Some(1800), message.max_age());
+        assert_eq!(None, message.header("NT"));
+
+        assert_eq!(None, Message::parse(""));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let options = SearchOptions {
+            timeout: Duration::from_millis(500),
+            repeats: 3,
+            target: simulator.ssdp_addr(),
+            ..SearchOptions::default()
+        };
+
+        let responses = search(&[SKY_PLAY.to_string(), SKY_BROWSE.to_string()], &options).await.unwrap();
+
+        let mut search_targets: Vec<_> = responses.iter().filter_map(|response| response.header("ST")).collect();
+        search_targets.sort_unstable();
+        assert_eq!(vec![SKY_BROWSE.to_string(), SKY_PLAY.to_string()], search_targets);
+        assert!(responses.iter().all(|response| response.header("LOCATION") == Some(simulator.description_url().as_str())));
+    }
+}
@@ -0,0 +1,302 @@
+use super::common::{SKY_BROWSE, SKY_PLAY};
+use super::common::errors::Result;
+use super::ssdp::{self, Message};
+
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, timeout_at};
+use url::Url;
+
+// How often to forget boxes whose announcements have expired
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/**
+ * An SSDP NOTIFY announcement of a `SkyPlay` or `SkyBrowse` service
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    Alive { udn: String, service_type: String, location: Url, max_age: Option<u64> },
+    ByeBye { udn: String, service_type: String },
+}
+
+impl Notification {
+
+    /**
+     * Parse a datagram, ignoring anything other than NOTIFY messages for SkyBox services
+     */
+    pub fn parse(datagram: &str) -> Option<Notification> {
+        let message = Message::parse(datagram)?;
+        if message.start_line != "NOTIFY * HTTP/1.1" {
+            return None;
+        }
+
+        let service_type = message.header("NT")?.to_owned();
+        if service_type != SKY_PLAY.to_string() && service_type != SKY_BROWSE.to_string() {
+            return None;
+        }
+        let udn = message.udn()?.to_owned();
+
+        match message.header("NTS")? {
+            "ssdp:alive" => Some(Notification::Alive {
+                udn, service_type,
+                location: Url::parse(message.header("LOCATION")?).ok()?,
+                max_age: message.max_age()
+            }),
+            "ssdp:byebye" => Some(Notification::ByeBye { udn, service_type }),
+            _ => None
+        }
+    }
+}
+
+/**
+ * A SkyBox announcing itself, by host
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sighting {
+    pub host: String,
+    pub locations: BTreeSet<Url>, // Description of each device announced
+    pub services: BTreeMap<String, String>, // Service type -> UDN
+    pub last_seen: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/**
+ * How the table of boxes changed
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Appeared(String),
+    Left(String),
+}
+
+/**
+ * The SkyBoxes currently announcing themselves
+ */
+#[derive(Debug, Default)]
+pub struct NetworkTable {
+    sightings: BTreeMap<String, Sighting>,
+}
+
+impl NetworkTable {
+
+    pub fn sightings(&self) -> impl Iterator<Item = &Sighting> {
+        self.sightings.values()
+    }
+
+    pub fn get(&self, host: &str) -> Option<&Sighting> {
+        self.sightings.get(host)
+    }
+
+    /**
+     * Apply a notification received at `now`, giving any changes to the boxes present
+     */
+    pub fn apply(&mut self, notification: Notification, now: DateTime<Utc>) -> Vec<Change> {
+        match notification {
+            Notification::Alive { udn, service_type, location, max_age } => {
+                let host = match location.host_str() {
+                    Some(host) => host.to_owned(),
+                    None => return vec![]
+                };
+
+                // A box given a new address leaves its old one
+                let mut changes = self.remove_services(|other_host, other_udn| other_host != host && other_udn == udn);
+
+                let sighting = self.sightings.entry(host.clone()).or_insert_with(|| {
+                    changes.push(Change::Appeared(host.clone()));
+                    Sighting {
+                        host,
+                        locations: BTreeSet::new(),
+                        services: BTreeMap::new(),
+                        last_seen: now,
+                        expires: None
+                    }
+                });
+                sighting.locations.insert(location);
+                sighting.services.insert(service_type, udn);
+                sighting.last_seen = now;
+                sighting.expires = max_age.map(|max_age| now + chrono::Duration::seconds(max_age as i64));
+
+                changes
+            }
+            Notification::ByeBye { udn, service_type } => {
+                self.sightings.values_mut()
+                    .filter(|sighting| sighting.services.get(&service_type) == Some(&udn))
+                    .for_each(|sighting| { sighting.services.remove(&service_type); });
+                self.remove_services(|_, _| false)
+            }
+        }
+    }
+
+    /**
+     * Forget boxes whose announcements have expired by `now`
+     */
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<Change> {
+        let expired: Vec<String> = self.sightings.values()
+            .filter(|sighting| sighting.expires.iter().any(|expires| *expires <= now))
+            .map(|sighting| sighting.host.clone())
+            .collect();
+
+        for host in expired.iter() {
+            self.sightings.remove(host);
+        }
+        expired.into_iter().map(Change::Left).collect()
+    }
+
+    /**
+     * Remove the services matching `(host, udn)`, then any box left without services
+     */
+    fn remove_services<P>(&mut self, predicate: P) -> Vec<Change>
+        where P: Fn(&str, &str) -> bool
+    {
+        for sighting in self.sightings.values_mut() {
+            let host = sighting.host.clone();
+            sighting.services.retain(|_, udn| !predicate(&host, udn));
+        }
+
+        let empty: Vec<String> = self.sightings.values()
+            .filter(|sighting| sighting.services.is_empty())
+            .map(|sighting| sighting.host.clone())
+            .collect();
+
+        for host in empty.iter() {
+            self.sightings.remove(host);
+        }
+        empty.into_iter().map(Change::Left).collect()
+    }
+}
+
+/**
+ * Listen passively for SkyBoxes announcing their arrival and departure
+ */
+pub struct Watcher {
+    socket: UdpSocket,
+    table: NetworkTable,
+    pending: VecDeque<Change>,
+    next_expiry: Instant,
+}
+
+impl Watcher {
+
+    /**
+     * Join the SSDP multicast group on `interface`, or on the interface the OS picks given `0.0.0.0`
+     */
+    pub fn bind(interface: Ipv4Addr) -> Result<Watcher> {
+        Ok(Watcher::new(ssdp::bind_multicast(interface)?))
+    }
+
+    /**
+     * Listen on an already bound socket
+     */
+    pub fn new(socket: UdpSocket) -> Watcher {
+        Watcher{socket, table: NetworkTable::default(), pending: VecDeque::new(), next_expiry: Instant::now() + EXPIRY_INTERVAL}
+    }
+
+    pub fn table(&self) -> &NetworkTable {
+        &self.table
+    }
+
+    /**
+     * Wait for the next change to the table of boxes.
+     * Expired boxes are forgotten every `EXPIRY_INTERVAL`, however busy the network.
+     */
+    pub async fn next_change(&mut self) -> Result<Change> {
+        let mut buffer = [0u8; 2048];
+
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Ok(change);
+            }
+
+            if Instant::now() >= self.next_expiry {
+                self.next_expiry = Instant::now() + EXPIRY_INTERVAL;
+                self.pending.extend(self.table.expire(Utc::now()));
+                continue;
+            }
+
+            if let Ok(received) = timeout_at(self.next_expiry, self.socket.recv_from(&mut buffer)).await {
+                let (len, _) = received?;
+                if let Some(notification) = Notification::parse(&String::from_utf8_lossy(&buffer[..len])) {
+                    self.pending.extend(self.table.apply(notification, Utc::now()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, RECORDINGS};
+
+    fn alive(host: &str, udn: &str, service_type: &str) -> Notification {
+        Notification::Alive {
+            udn: udn.into(),
+            service_type: service_type.into(),
+            location: Url::parse(&format!("http://{}:49153/description0.xml", host)).unwrap(),
+            max_age: Some(1800)
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let browse = SKY_BROWSE.to_string();
+        let notification = Notification::parse(&format!("NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCACHE-CONTROL: max-age=1800\r\nLOCATION: http://192.168.2.152:49153/description0.xml\r\nNT: {}\r\nNTS: ssdp:alive\r\nUSN: uuid:BEEF::{}\r\n\r\n", browse, browse));
+        assert_eq!(Some(alive("192.168.2.152", "uuid:BEEF", &browse)), notification);
+
+        let notification = Notification::parse(&format!("NOTIFY * HTTP/1.1\r\nNT: {}\r\nNTS: ssdp:byebye\r\nUSN: uuid:BEEF::{}\r\n\r\n", browse, browse));
+        assert_eq!(Some(Notification::ByeBye { udn: "uuid:BEEF".into(), service_type: browse.clone() }), notification);
+
+        assert_eq!(None, Notification::parse("NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\nUSN: uuid:BEEF\r\n\r\n"));
+        assert_eq!(None, Notification::parse(&format!("HTTP/1.1 200 OK\r\nST: {}\r\n\r\n", browse)));
+    }
+
+    #[test]
+    fn test_apply() {
+        let (play, browse) = (SKY_PLAY.to_string(), SKY_BROWSE.to_string());
+        let now = DateTime::parse_from_rfc3339("2021-03-01T20:00:00Z").unwrap().with_timezone(&Utc);
+        let mut table = NetworkTable::default();
+
+        assert_eq!(vec![Change::Appeared("192.168.2.152".into())], table.apply(alive("192.168.2.152", "uuid:BEEF", &browse), now));
+        assert!(table.apply(alive("192.168.2.152", "uuid:BEEF", &play), now).is_empty());
+        assert_eq!(2, table.get("192.168.2.152").unwrap().services.len());
+        assert_eq!(1, table.get("192.168.2.152").unwrap().locations.len());
+
+        // Departing one service at a time
+        let byebye = |service_type: &str| Notification::ByeBye { udn: "uuid:BEEF".into(), service_type: service_type.into() };
+        assert!(table.apply(byebye(&play), now).is_empty());
+        assert_eq!(vec![Change::Left("192.168.2.152".into())], table.apply(byebye(&browse), now));
+        assert_eq!(0, table.sightings().count());
+
+        // Moving to a new address
+        table.apply(alive("192.168.2.152", "uuid:BEEF", &browse), now);
+        assert_eq!(vec![Change::Left("192.168.2.152".into()), Change::Appeared("192.168.2.99".into())],
+            table.apply(alive("192.168.2.99", "uuid:BEEF", &browse), now));
+    }
+
+    #[test]
+    fn test_expire() {
+        let browse = SKY_BROWSE.to_string();
+        let now = DateTime::parse_from_rfc3339("2021-03-01T20:00:00Z").unwrap().with_timezone(&Utc);
+        let mut table = NetworkTable::default();
+        table.apply(alive("192.168.2.152", "uuid:BEEF", &browse), now);
+
+        assert!(table.expire(now + chrono::Duration::seconds(1799)).is_empty());
+        assert_eq!(vec![Change::Left("192.168.2.152".into())], table.expire(now + chrono::Duration::seconds(1800)));
+    }
+
+    #[tokio::test]
+    async fn test_next_change() {
+        let simulator = Simulator::start(RECORDINGS).await.unwrap();
+        let mut watcher = Watcher::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let watcher_addr = watcher.socket.local_addr().unwrap();
+
+        simulator.announce(watcher_addr, true).await.unwrap();
+        assert_eq!(Change::Appeared("127.0.0.1".into()), watcher.next_change().await.unwrap());
+
+        simulator.announce(watcher_addr, false).await.unwrap();
+        assert_eq!(Change::Left("127.0.0.1".into()), watcher.next_change().await.unwrap());
+    }
+}